@@ -1,11 +1,18 @@
 use log::{debug, trace};
-use reqwest::Method;
+use std::collections::HashMap;
+use std::ffi::OsString;
 use std::path::PathBuf;
 use crate::config;
+use crate::oauth;
+use crate::signing;
 use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
 use structopt::StructOpt;
 use crate::errors::{Error, HurlResult};
-use crate::session::make_safe_pathname;
+use crate::session;
+use crate::source;
+use crate::variables::{parse_capture, Capture};
 
 ///A comand line HTTP client 
 #[derive(StructOpt, Debug)]
@@ -25,29 +32,156 @@ pub verbose: u8,
 #[structopt(short, long)]
 pub form:bool,
 
+/// The method to use with the URL form, e.g. PURGE or PROPFIND
+///
+/// Without this the method is GET, or POST when there are data
+/// parameters. `hurl PURGE url` is the same as `hurl --method PURGE url`
+/// for methods which have no subcommand, and neither can be used with a
+/// method subcommand or `run`. PROPFIND is sent with Depth: 1 unless the
+/// parameters, the session or the configuration file give a Depth
+/// header, and raw bodies of WebDAV methods such as PROPFIND, PROPPATCH,
+/// REPORT and LOCK default to application/xml.
+#[structopt(long, short = "X", value_name = "METHOD", parse(try_from_str = parse_method))]
+pub method: Option<reqwest::Method>,
+
+/// Stream the contents of FILE as the request body, - for stdin
+///
+/// The file is not read into memory, so this works for files of any
+/// size. Without a method the request is a PUT, and the Content-Type is
+/// application/octet-stream unless given as a header, in the session or
+/// in the configuration file. Progress is shown on stderr unless --quiet
+/// is given. With digest, OAuth, aws-sigv4 or --sign the file is read
+/// again to hash or resend it, and stdin is read into memory.
+#[structopt(long, value_name = "FILE", parse(from_os_str))]
+pub upload: Option<PathBuf>,
+
+/// How query parameters given more than once are encoded
+///
+/// For ids==1 ids==2: repeat sends ids=1&ids=2, brackets sends
+/// ids[]=1&ids[]=2 and comma sends ids=1,2.
+#[structopt(
+    long,
+    default_value = "repeat",
+    possible_values = &["repeat", "brackets", "comma"],
+    case_insensitive = true
+)]
+pub query_array: QueryArray,
+
+/// Add the query parameters in a JSON or TOML file
+///
+/// The file holds a map of names to values. Arrays become repeated
+/// parameters and objects are sent as JSON. Files ending in .toml are
+/// read as TOML, anything else as JSON. Query parameters on the command
+/// line replace those in the file with the same name.
+#[structopt(long, value_name = "FILE", parse(from_os_str))]
+pub query_file: Option<PathBuf>,
+
 ///Basic autifation
 /// 
 /// A string of the form 'usersname:pasword'. if only 
 /// 'username' is given then you will be prompted
 /// for a password. If you wish  to use no password 
 /// then use form 'username:'
+///
+/// The form 'keyring:service/username' reads the password from the
+/// OS keyring, see `hurl auth store`.
 #[structopt(long, short)]
 pub auth: Option<String>,
 
+/// Authenticate with a token from the OAuth2 provider NAME
+///
+/// Providers are configured in [oauth.NAME] tables of the configuration
+/// file with the keys token_url, client_id, client_secret, scopes,
+/// grant ("client_credentials", "password" or "authorization_code"),
+/// username, password, authorization_url, redirect_port and url, the
+/// URL `hurl auth login` stores tokens for when it is not given one.
+/// Tokens are cached in the session and refreshed when they expire or
+/// are rejected.
+#[structopt(long, value_name = "NAME")]
+pub oauth: Option<String>,
+
+/// The authentication scheme: basic, digest, bearer or aws-sigv4
+///
+/// By default --auth is sent as basic and --token as bearer
+/// authentication. Digest authentication uses the credentials from
+/// --auth, or .netrc, to answer the challenge of the server.
+///
+/// aws-sigv4 signs the request with AWS Signature Version 4 using the
+/// credentials from AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, or from
+/// ~/.aws/credentials, and needs --aws-region and --aws-service.
+#[structopt(
+    long,
+    possible_values = &["basic", "digest", "bearer", "aws-sigv4"],
+    case_insensitive = true
+)]
+pub auth_type: Option<AuthType>,
+
+/// The AWS region to sign requests for, e.g. us-east-1
+#[structopt(long, env = "AWS_REGION")]
+pub aws_region: Option<String>,
+
+/// The AWS service to sign requests for, e.g. s3 or execute-api
+#[structopt(long)]
+pub aws_service: Option<String>,
+
+/// Sign requests with the signing scheme NAME
+///
+/// Schemes are configured in [signing.NAME] tables of the configuration
+/// file with the keys algorithm ("hmac-sha256" or "hmac-sha512"),
+/// secret, template, encoding ("hex", "base64" or "base64url"), header,
+/// prefix, timestamp_header, timestamp_format ("unix", "millis" or
+/// "rfc3339") and nonce_header. The template is filled in with
+/// {method}, {path}, {query}, {host}, {timestamp}, {nonce},
+/// {body_sha256}, {body_sha512} and {header:Name} before signing, and
+/// {{ stands for a literal {.
+#[structopt(long, value_name = "NAME")]
+pub sign: Option<String>,
+
+/// Read credentials from this file instead of ~/.netrc
+///
+/// When no --auth or --token is given, the login and password of the
+/// `machine` entry for the request host are used for basic authentication.
+#[structopt(long, parse(from_os_str))]
+pub netrc_file: Option<PathBuf>,
+
+/// Do not read credentials from .netrc
+#[structopt(long)]
+pub ignore_netrc: bool,
+
 /// Bearer token autentification
 /// 
 /// A token wich will be sent as "Bearaer <token>" in 
 /// the authorization header. 
+///
+/// The form 'keyring:service/username' reads the token from the
+/// OS keyring, see `hurl auth store`.
 #[structopt(short, long)]
 pub token: Option<String>,
+
+/// Print the header and claims of a JWT --token before sending it
+///
+/// Expired tokens are warned about whether or not this is given.
+#[structopt(long)]
+pub show_token_claims: bool,
  ///Sesion name
- #[structopt(logs)]
+ #[structopt(long)]
  pub session: Option<String>,
 
  ///Sesion storage location
  #[structopt(long,parse(from_os_str))]
  pub session_dir: Option<PathBuf>,
 
+ /// Passphrase used to encrypt stored sessions
+ ///
+ /// When set sessions are saved encrypted, and it is needed to read
+ /// sessions which were saved encrypted.
+ #[structopt(long, env = "HURL_SESSION_KEY", hide_env_values = true)]
+ pub session_key: Option<String>,
+
+ /// Prompt for the passphrase used to encrypt stored sessions
+ #[structopt(long)]
+ pub ask_session_key: bool,
+
  /// if true then use the store session to argument the request
  /// but not modify by stored
  #[structopt(long)]
@@ -59,6 +193,66 @@ pub token: Option<String>,
 /// is set then https will be used instead. 
 #[structopt(short, long)]
 pub secure: bool,
+/// Fail unless the response has this status code
+#[structopt(long)]
+pub expect_status: Option<u16>,
+
+/// Fail unless the response has this header
+///
+/// Given as 'Name: value', or just 'Name' to only check that the
+/// header is present. A value without parameters matches by media type,
+/// so 'Content-Type: application/json' accepts a charset, and a value
+/// ending in * matches any value starting with the rest, e.g.
+/// 'Location: https://example.com/items/*'. May be repeated.
+#[structopt(long, number_of_values = 1)]
+pub expect_header: Vec<String>,
+
+/// Fail unless the JSON response satisfies this expression
+///
+/// A jq-like path compared with == or != to a JSON value,
+/// e.g. '.data.id == 5' or '.items[0].name != "bob"'. A path on its
+/// own checks that the value exists. May be repeated.
+#[structopt(long, number_of_values = 1)]
+pub expect_json: Vec<String>,
+
+/// Fail unless the response body contains this text. May be repeated.
+#[structopt(long, number_of_values = 1)]
+pub expect_body_contains: Vec<String>,
+
+/// Load environment variables from a .env file
+///
+/// Each line has the form KEY=VALUE. Variables already set in the
+/// environment take precedence over the file. The file is loaded before
+/// the other options are read, so it can set HURL_PROFILE, AWS_REGION
+/// and the other variables options fall back to.
+// Read by `App::env_file_arg` before the arguments are parsed
+#[allow(dead_code)]
+#[structopt(long, parse(from_os_str))]
+pub env_file: Option<PathBuf>,
+
+/// Run the commands of key:<command parameters for their values
+///
+/// Without this a value such as Link:<https://example.com> is sent as
+/// it is.
+#[structopt(long)]
+pub run_commands: bool,
+
+/// Fail when a {{...}} or ${...} variable is undefined
+///
+/// Without this an undefined variable is replaced by an empty string.
+#[structopt(long)]
+pub strict: bool,
+
+/// Capture a value from the response into a session variable
+///
+/// Given as 'name=.json.path', 'name=header:Header-Name' or
+/// 'name=cookie:cookie-name'. Captured values can be referenced as
+/// {{name}} in the URL, header values and parameter values of later
+/// requests using the same session. Nothing is captured from a response
+/// which fails an --expect-* check. May be repeated.
+#[structopt(long, number_of_values = 1, parse(try_from_str = parse_capture))]
+pub capture: Vec<Capture>,
+
 ///Configuration file
 /// 
 /// A TOML file which is stored by default at HOME/.config/hurl/confiig
@@ -70,8 +264,18 @@ pub secure: bool,
 /// token: string
 /// secure: bool
 /// 
+/// Named profiles can be defined in [profiles.NAME] tables and
+/// selected with --profile. A profile supports the keys base_url,
+/// headers, auth, token, insecure, cacert, proxy, oauth and sign, which
+/// take precedence over the top level keys. Authentication given on the
+/// command line replaces that of the profile, and a profile's replaces
+/// the top level auth and token.
 /// 
-/// 
+/// Headers and query parameters added to every request can be given
+/// in [headers] and [query] tables, or only for matching hosts in
+/// [hosts."*.example.com".headers] and [hosts."*.example.com".query].
+/// Parameters on the command line take precedence over the session,
+/// which takes precedence over the configuration file.
 /// 
 /// Each optional has the same meaning as the same corresponding configuration
 /// optioal with the same name. The verbose setting is a number from 0
@@ -80,11 +284,46 @@ pub secure: bool,
 /// 
 #[structopt(short,long, env="HURL_CONFIG", parse(from_os_str))]
 pub config: Option<PathBuf>,
+
+/// Configuration profile to use
+#[structopt(long, env = "HURL_PROFILE")]
+pub profile: Option<String>,
+
+/// Base URL that relative URLs such as /users are resolved against
+#[structopt(long)]
+pub base_url: Option<String>,
+
+/// Proxy to send requests through, e.g. http://localhost:8080
+#[structopt(long)]
+pub proxy: Option<String>,
+
+/// Accept invalid TLS certificates
+#[structopt(long)]
+pub insecure: bool,
+
+/// An additional PEM encoded root certificate to trust
+#[structopt(long, parse(from_os_str))]
+pub cacert: Option<PathBuf>,
+
+/// Headers and query parameters added to every request by the
+/// configuration file
+#[structopt(skip)]
+pub config_defaults: config::Defaults,
+
+/// Defaults from the configuration file for hosts matching a pattern
+#[structopt(skip)]
+pub host_defaults: Vec<(String, config::Defaults)>,
 /// The HTTP method to use: GET, POST, HEAD, PUT, PATCH, DELETE
+/// or one of the other hurl commands
 #[structopt(subcommand)]
-pub cmd: Option<Method>, 
+pub cmd: Option<Command>, 
 
 /// The URL to issue a request to if a method subcomand is not specified
+///
+/// A URL of the form @NAME runs the request saved as [requests.NAME]
+/// in the configuration file, with any parameters given added to it.
+///
+/// Environment variables can be referenced as {{env.NAME}} or ${NAME}.
 pub url: Option<String>,
 /// The parameters for the request if a method subcommand is not specified
 /// 
@@ -95,18 +334,57 @@ pub url: Option<String>,
 /// Header --key value 
 /// 
 /// e.a. X-API-TOKEN:abc123
+///
+/// Header: without a value stops the header being sent, including one
+/// stored in the session, and Header; sends it with an empty value.
+/// User-Agent and Accept are always sent, and can only be replaced.
+///
+/// Values from a file or a command -- key:@filename, key:<command
+///
+/// e.g. Authorization:@token.txt or X-Sig:<"sign-request --now" with
+/// trailing newlines removed, and a filename of - reads stdin. Query
+/// parameters take key==@filename and key==<command, data fields
+/// key=<command and raw JSON key:=<command. Commands are only run with
+/// --run-commands, otherwise the value is sent as given, starting with <.
+/// They are not run through a shell. Files and commands are read once
+/// the command line is parsed, never from values captured into a session
+/// or given in a request file.
+///
+/// A backslash escapes =, :, @, < or \ in a key or value, e.g.
+/// X-Tag:\<b> sends the header X-Tag: <b>.
+///
+/// Environment variables can be referenced as {{env.NAME}} or ${NAME}.
 /// 
 /// File upload -- key@filename 
 /// 
 /// this simulated a file upload via multipart/from-data and requires --from 
+///
+/// e.g. avatar@photo.jpg;type=image/png;filename=avatar.png sets the content
+/// type and file name of the part, and avatar@- reads the file from stdin
+/// and names it stdin.
+/// In a multipart form key:=value and key:=@filename are sent as
+/// application/json parts.
 /// 
 /// Query parameter --key= value 
 /// 
 /// e.g. foo = bar becomes  {"foo":"bar"} for JSON or form encoded 
 /// 
+/// URL query parameter -- key==value
+///
+/// e.g. page==2 becomes ?page=2
+///
+/// Query parameters can be given more than once, see --query-array, and
+/// key=:=value sends JSON, e.g. filter=:={"a":1} becomes ?filter=%7B%22a%22%3A1%7D.
+/// Before =:= was a separator key=:=value sent the data field key with the
+/// value :=value, use key=\:=value for that now.
+///
 /// Data field from file -- key=@filename
 /// 
 /// e.g. foo = @bar.txt becomes {"foo":"the content of bar.txt"} or from encoded
+///
+/// The file must be UTF-8 text. Binary files can be sent base64 encoded
+/// with key=@@filename, e.g. foo=@@bar.png becomes {"foo":"iVBORw0KGgo..."}
+/// A filename of - reads stdin, for these and for key:=@filename.
 /// 
 /// Raw JSON data where the value should be parsed to JSON first --key:value
 /// 
@@ -117,23 +395,155 @@ pub url: Option<String>,
 /// e.g. foo:=@bar.json becomes {"foo":{"bar":this is from bar.json}}
 
 #[structopt(parse(try_from_str = parse_param))]
-pub parametrs: Vec<Parameter>, 
+pub parameters: Vec<Parameter>, 
+
+/// Requests saved in the configuration file, invoked as @NAME
+#[structopt(skip)]
+pub saved_requests: HashMap<String, config::SavedRequest>,
+
+/// OAuth2 providers from the configuration file
+#[structopt(skip)]
+pub oauth_providers: HashMap<String, oauth::Provider>,
+
+/// Request signing schemes from the configuration file
+#[structopt(skip)]
+pub signing_schemes: HashMap<String, signing::Scheme>,
+
+/// The method of the saved request being run, if it specifies one
+#[structopt(skip)]
+pub saved_method: Option<reqwest::Method>,
+
+/// Query parameters loaded from --query-file
+#[structopt(skip)]
+pub query_file_parameters: Vec<Parameter>,
 }
 
 
 
 
 impl App{
-    pub fn validate(&mut self)-> HurlResult{
+    /// Parse the command line, accepting any method as `hurl METHOD url`
+    ///
+    /// Clap takes an unknown METHOD for the URL, so a method without a
+    /// subcommand is turned into --method METHOD if that parses.
+    pub fn from_args_with_methods() -> App{
+        let args: Vec<OsString> = std::env::args_os().collect();
+        App::parse_with_methods(&args).unwrap_or_else(App::from_args)
+    }
+
+    /// The value of --env-file in `args`, found before they are parsed
+    ///
+    /// The file is loaded first so that it can set the variables options
+    /// fall back to, such as HURL_PROFILE or AWS_REGION.
+    pub fn env_file_arg(args: &[OsString]) -> Option<PathBuf>{
+        let mut iter = args.iter().skip(1);
+        while let Some(arg) = iter.next(){
+            let arg = match arg.to_str(){
+                Some(arg) => arg,
+                None => continue,
+            };
+            if arg == "--"{
+                break;
+            }
+            if arg == "--env-file"{
+                return iter.next().map(PathBuf::from);
+            }
+            if let Some(path) = arg.strip_prefix("--env-file="){
+                return Some(PathBuf::from(path));
+            }
+        }
+        None
+    }
+
+    /// The first positional argument as the method, if it looks like one
+    ///
+    /// The rewrite is only kept when the argument after it becomes the
+    /// URL, so a parameter value or the URL itself is never taken for a
+    /// method.
+    fn parse_with_methods(args: &[OsString]) -> Option<App>{
+        for (i, arg) in args.iter().enumerate().skip(1){
+            let is_method = arg
+                .to_str()
+                .is_some_and(|a|is_method_token(a) && !METHOD_SUBCOMMANDS.contains(&a));
+            if !is_method{
+                continue;
+            }
+            let mut rewritten = args.to_vec();
+            rewritten.insert(i, OsString::from("--method"));
+            let app = match App::from_iter_safe(&rewritten){
+                Ok(app) => app,
+                Err(_) => continue,
+            };
+            let next = args.get(i + 1).and_then(|a|a.to_str());
+            if app.url.is_some() && app.url.as_deref() == next{
+                trace!("Using {:?} as the method", arg);
+                return Some(app);
+            }
+        }
+        None
+    }
+
+    /// Check the options once the configuration file has been merged in
+    pub fn validate(&mut self)-> HurlResult<()>{
         if self.cmd.is_none() && self.url.is_none(){
             return Err(Error::MissingUrlAndCommand);
         }
+        if !self.capture.is_empty() && self.session.is_none(){
+            return Err(Error::CaptureWithoutSession);
+        }
+        if self.method.is_some(){
+            match self.cmd{
+                Some(Command::Method(_)) => {
+                    return Err(Error::ConflictingOptions("--method", "a method subcommand"))
+                }
+                Some(Command::Run(_)) => return Err(Error::ConflictingOptions("--method", "run")),
+                _ => {}
+            }
+        }
+        if self.oauth.is_some(){
+            if self.auth_type.is_some(){
+                return Err(Error::ConflictingOptions("--oauth", "--auth-type"));
+            }
+            if self.auth.is_some(){
+                return Err(Error::ConflictingOptions("--oauth", "--auth"));
+            }
+            if self.token.is_some(){
+                return Err(Error::ConflictingOptions("--oauth", "--token"));
+            }
+        }
+        if self.sign.is_some(){
+            if self.oauth.is_some(){
+                return Err(Error::ConflictingOptions("--sign", "--oauth"));
+            }
+            match self.auth_type{
+                Some(AuthType::Digest) => {
+                    return Err(Error::ConflictingOptions("--sign", "--auth-type digest"))
+                }
+                Some(AuthType::AwsSigV4) => {
+                    return Err(Error::ConflictingOptions("--sign", "--auth-type aws-sigv4"))
+                }
+                _ => {}
+            }
+        }
         Ok(())
     }
-    pub fn process_config_file(&mut self){
+    pub fn process_config_file(&mut self) -> HurlResult<()>{
         let config_path = config::config_file(self);
-        let config_opt = config::read_config_file(config_path);
+        let config_opt = config::read_config_file(config_path)?;
+        if config_opt.is_none(){
+            if let Some(name) = &self.profile{
+                return Err(Error::UnknownProfile(name.clone()));
+            }
+        }
         if let Some(mut config) = config_opt{
+            if let Some(name) = &self.profile{
+                let profile = config
+                    .profiles
+                    .remove(name)
+                    .ok_or_else(|| Error::UnknownProfile(name.clone()))?;
+                trace!("Using profile {}", name);
+                self.apply_profile(profile);
+            }
             if self.verbose == 0{
                 if let Some(v) = config.verbose {
                     self.verbose = v;
@@ -141,7 +551,7 @@ impl App{
             }
             if !self.form{
                 if let Some(f) = config.form{
-                    self.secure = f;    
+                    self.form = f;
                 }
             }
             if !self.secure {
@@ -149,17 +559,176 @@ impl App{
                     self.secure = s;
                 }
             }
-            if self.auth.is_none(){
-                self.auth = config.auth.take();
+            self.inherit_auth(config.auth.take(), config.token.take(), None, None);
+            self.config_defaults.fill_from(config::Defaults{
+                headers: config.headers,
+                query: config.query,
+            });
+            self.host_defaults = config.hosts.into_iter().collect();
+            self.saved_requests = config.requests;
+            self.oauth_providers = config.oauth;
+            self.signing_schemes = config.signing;
+        }
+        Ok(())
+   }
+
+    /// The name the session for the request is stored under
+    pub fn host(&self) -> String{
+        if let Some(url) = &self.url{
+            session::host_key(self, url)
+        } else if let Some(Command::Method(cmd))= &self.cmd{
+            session::host_key(self, &cmd.data().url)
+        } else {
+            unreachable!();
+        }
+    }
+
+    /// Replace a URL of the form @NAME with the saved request NAME
+    ///
+    /// Parameters from the command line are added to the saved ones and
+    /// replace saved parameters of the same kind with the same key.
+    pub fn resolve_saved_request(&mut self) -> HurlResult<()>{
+        let name = match &self.url{
+            Some(url) if url.starts_with('@') => url[1..].to_owned(),
+            _ => return Ok(()),
+        };
+        let saved = self
+            .saved_requests
+            .remove(&name)
+            .ok_or_else(|| Error::UnknownSavedRequest(name.clone()))?;
+        trace!("Using saved request {}", name);
+
+        let mut parameters = saved
+            .parameters()?
+            .into_iter()
+            .map(|p|p.run_command(self.run_commands))
+            .collect::<HurlResult<Vec<Parameter>>>()?;
+        let given = &self.parameters;
+        parameters.retain(|p| !given.iter().any(|g|g.overrides(p)));
+        parameters.append(&mut self.parameters);
+        self.parameters = parameters;
+        self.saved_method = saved.method()?;
+        self.url = Some(saved.url);
+        Ok(())
+    }
+
+    fn apply_profile(&mut self, profile: config::Profile){
+        if self.base_url.is_none(){
+            self.base_url = profile.base_url;
+        }
+        self.inherit_auth(profile.auth, profile.token, profile.oauth, profile.sign);
+        if self.proxy.is_none(){
+            self.proxy = profile.proxy;
+        }
+        if !self.insecure{
+            if let Some(i) = profile.insecure{
+                self.insecure = i;
+            }
+        }
+        if self.cacert.is_none(){
+            self.cacert = profile.cacert;
+        }
+        self.config_defaults.fill_from(config::Defaults{
+            headers: profile.headers,
+            query: profile.query,
+        });
+    }
+
+    /// Take the authentication settings of a profile or the configuration
+    /// file, unless those given on the command line or in the profile
+    /// already choose how to authenticate
+    ///
+    /// Settings which conflict with the chosen ones are not inherited, so
+    /// that only conflicts given at the same level are reported.
+    fn inherit_auth(
+        &mut self,
+        auth: Option<String>,
+        token: Option<String>,
+        oauth: Option<String>,
+        sign: Option<String>,
+    ){
+        if self.auth.is_none() && self.token.is_none() && self.oauth.is_none(){
+            self.auth = auth;
+            self.token = token;
+            if self.auth_type.is_none() && self.sign.is_none(){
+                self.oauth = oauth;
             }
-            if self.token.is_none(){
-                self.token = config.token.take();
+        }
+        let signs = !matches!(self.auth_type, Some(AuthType::Digest) | Some(AuthType::AwsSigV4));
+        if self.sign.is_none() && self.oauth.is_none() && signs{
+            self.sign = sign;
+        }
+    }
+
+    /// Run the commands of `key:<command` parameters once the command line
+    /// has been parsed, or keep their values as given without
+    /// --run-commands
+    pub fn resolve_commands(&mut self) -> HurlResult<()>{
+        let run = self.run_commands;
+        let mut given = vec![&mut self.parameters];
+        if let Some(Command::Method(method)) = &mut self.cmd{
+            given.push(&mut method.data_mut().parameters);
+        }
+        for parameters in given{
+            *parameters = std::mem::take(parameters)
+                .into_iter()
+                .map(|p|p.run_command(run))
+                .collect::<HurlResult<Vec<Parameter>>>()?;
+        }
+        Ok(())
+    }
+
+    /// Read the parameters of --query-file
+    pub fn load_query_file(&mut self) -> HurlResult<()>{
+        let path = match &self.query_file{
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let invalid = |msg: String| Error::InvalidQueryFile(path.clone(), msg);
+        let content = std::fs::read_to_string(path)?;
+        let is_toml = path.extension().is_some_and(|e|e == "toml");
+        let map: serde_json::Map<String, serde_json::Value> = if is_toml{
+            let value: toml::Value = toml::from_str(&content).map_err(|e|invalid(e.to_string()))?;
+            serde_json::from_value(serde_json::to_value(value)?).map_err(|e|invalid(e.to_string()))?
+        } else {
+            serde_json::from_str(&content).map_err(|e|invalid(e.to_string()))?
+        };
+
+        let mut parameters = Vec::new();
+        for (key, value) in map{
+            let values = match value{
+                serde_json::Value::Array(values) => values,
+                value => vec![value],
+            };
+            for value in values{
+                parameters.push(match value{
+                    serde_json::Value::String(s) => Parameter::Query{key: key.clone(), value: s},
+                    serde_json::Value::Null => {
+                        return Err(invalid(format!("{} has no value", key)))
+                    }
+                    v @ serde_json::Value::Object(_) | v @ serde_json::Value::Array(_) => {
+                        Parameter::JsonQuery{key: key.clone(), value: v.to_string()}
+                    }
+                    v => Parameter::Query{key: key.clone(), value: v.to_string()},
+                });
             }
         }
-   }
+        trace!("Loaded {} query parameters from {}", parameters.len(), path.display());
+        self.query_file_parameters = parameters;
+        Ok(())
+    }
+
+    /// Prompt for the session key if asked to
+    pub fn resolve_session_key(&mut self) -> HurlResult<()>{
+        if self.ask_session_key && self.session_key.is_none(){
+            let key = rpassword::read_password_from_tty(Some("Session key: "))?;
+            self.session_key = Some(key);
+        }
+        Ok(())
+    }
 
     pub fn log_level(&self)-> Option<&'static str>{
-        if self.quiet || self.verbose <= 0{
+        if self.quiet || self.verbose == 0{
             return None;
         }
 
@@ -173,18 +742,272 @@ impl App{
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthType{
+    Basic,
+    Digest,
+    Bearer,
+    AwsSigV4,
+}
 
-pub fn host(&self) -> String{
-    if let Some(url) = &self.url{
-        make_safe_pathname(url)
-    } else if let Some(cmd)= &self.cmd{
-        make_safe_pathname(&cmd.data().url)
-    } else {
-        unreachable!();
+impl FromStr for AuthType{
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        match s.to_ascii_lowercase().as_str(){
+            "basic" => Ok(AuthType::Basic),
+            "digest" => Ok(AuthType::Digest),
+            "bearer" => Ok(AuthType::Bearer),
+            "aws-sigv4" => Ok(AuthType::AwsSigV4),
+            _ => Err(Error::UnknownAuthType(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for AuthType{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            AuthType::Basic => write!(f, "basic"),
+            AuthType::Digest => write!(f, "digest"),
+            AuthType::Bearer => write!(f, "bearer"),
+            AuthType::AwsSigV4 => write!(f, "aws-sigv4"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JwtAlgorithm{
+    Hs256,
+    Rs256,
+}
+
+impl FromStr for JwtAlgorithm{
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        match s.to_ascii_uppercase().as_str(){
+            "HS256" => Ok(JwtAlgorithm::Hs256),
+            "RS256" => Ok(JwtAlgorithm::Rs256),
+            _ => Err(Error::Jwt(format!("unsupported algorithm {}", s))),
+        }
+    }
+}
+
+/// How a query parameter with several values is encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryArray{
+    Repeat,
+    Brackets,
+    Comma,
+}
+
+impl FromStr for QueryArray{
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        match s.to_ascii_lowercase().as_str(){
+            "repeat" => Ok(QueryArray::Repeat),
+            "brackets" => Ok(QueryArray::Brackets),
+            "comma" => Ok(QueryArray::Comma),
+            _ => Err(Error::UnknownQueryArray(s.to_owned())),
+        }
     }
 }
+
+#[derive(StructOpt, Debug)]
+pub enum Command{
+    /// Run the requests described in a .http file
+    #[structopt(name = "run")]
+    Run(RunData),
+    /// Inspect and edit the configuration file
+    #[structopt(name = "config")]
+    Config(ConfigCommand),
+    /// Inspect and manage stored sessions
+    #[structopt(name = "session")]
+    Session(SessionCommand),
+    /// Manage credentials stored in the OS keyring
+    #[structopt(name = "auth")]
+    Auth(AuthCommand),
+    /// Decode and sign JSON Web Tokens
+    #[structopt(name = "jwt")]
+    Jwt(JwtCommand),
+    #[structopt(flatten)]
+    Method(Method),
+}
+
+#[derive(StructOpt, Debug)]
+pub enum AuthCommand{
+    /// Log in to an OAuth2 provider in a browser
+    ///
+    /// Uses the authorization code flow with PKCE, receiving the redirect
+    /// on a local listener. The tokens are stored in the --session used
+    /// with URL and later sent with --oauth NAME. The login is abandoned
+    /// if the redirect does not arrive within five minutes.
+    #[structopt(name = "login")]
+    Login{
+        /// The name of the [oauth.NAME] provider
+        #[structopt(long)]
+        provider: String,
+        /// The URL the session will be used with, by default the url of
+        /// the provider or else --base-url
+        url: Option<String>,
+    },
+    /// Store a password or token in the OS keyring
+    ///
+    /// The secret is prompted for and can then be used with
+    /// --auth keyring:SERVICE/USER or --token keyring:SERVICE/USER.
+    /// If HURL_KEYRING_FILE is set secrets are kept in that file instead.
+    #[structopt(name = "store")]
+    Store{
+        /// The keyring entry, given as SERVICE/USER
+        entry: String,
+    },
+    /// Remove a password or token from the OS keyring
+    #[structopt(name = "forget")]
+    Forget{
+        /// The keyring entry, given as SERVICE/USER
+        entry: String,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum JwtCommand{
+    /// Print the header and claims of a JWT
+    ///
+    /// The token is not verified. Without TOKEN the --token is decoded.
+    #[structopt(name = "decode")]
+    Decode{
+        token: Option<String>,
+    },
+    /// Sign a JWT for testing, e.g. against a local development server
+    ///
+    /// HS256 uses the contents of the key file, without a trailing
+    /// newline, as the secret and RS256 a PEM encoded RSA private key.
+    #[structopt(name = "sign")]
+    Sign{
+        /// A JSON file with the claims
+        #[structopt(long, parse(from_os_str))]
+        claims: PathBuf,
+        /// The secret or private key file
+        #[structopt(long, parse(from_os_str))]
+        key: PathBuf,
+        #[structopt(
+            long,
+            default_value = "HS256",
+            possible_values = &["HS256", "RS256"],
+            case_insensitive = true
+        )]
+        alg: JwtAlgorithm,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum SessionCommand{
+    /// List the stored sessions
+    #[structopt(name = "list")]
+    List,
+    /// Show the headers, cookies and auth stored in a session
+    #[structopt(name = "show")]
+    Show{
+        /// A URL, or host, on the server the session is for
+        host: String,
+        /// The session name
+        name: String,
+        /// Show secrets instead of masking them
+        #[structopt(long)]
+        reveal: bool,
+    },
+    /// Delete a session
+    #[structopt(name = "delete")]
+    Delete{
+        host: String,
+        name: String,
+    },
+    /// Rename a session
+    #[structopt(name = "rename")]
+    Rename{
+        host: String,
+        name: String,
+        new_name: String,
+    },
+    /// Remove every cookie from a session
+    #[structopt(name = "clear-cookies")]
+    ClearCookies{
+        host: String,
+        name: String,
+    },
+    /// Write a session as plaintext JSON to a file or stdout
+    ///
+    /// Secrets are masked as in show unless --reveal is given, which is
+    /// needed for the session to be imported again.
+    #[structopt(name = "export")]
+    Export{
+        host: String,
+        name: String,
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+        /// Write secrets instead of masking them
+        #[structopt(long)]
+        reveal: bool,
+    },
+    /// Encrypt stored plaintext sessions with the session key
+    ///
+    /// Without a host every session is encrypted, and without a name
+    /// every session for the host.
+    #[structopt(name = "encrypt")]
+    Encrypt{
+        host: Option<String>,
+        name: Option<String>,
+    },
+    /// Read a session previously written by export
+    #[structopt(name = "import")]
+    Import{
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Store the session for this host instead of the exported one
+        #[structopt(long)]
+        host: Option<String>,
+        /// Store the session under this name instead of the exported one
+        #[structopt(long)]
+        name: Option<String>,
+        /// Replace a stored session of the same name
+        #[structopt(long)]
+        force: bool,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum ConfigCommand{
+    /// Print the effective configuration and where each value comes from
+    #[structopt(name = "show")]
+    Show,
+    /// Print the path of the configuration file
+    #[structopt(name = "path")]
+    Path,
+    /// Open the configuration file in $VISUAL or $EDITOR and validate it
+    #[structopt(name = "edit")]
+    Edit,
+    /// Check that the configuration file is valid
+    #[structopt(name = "validate")]
+    Validate,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct RunData{
+    /// The .http file to read requests from
+    #[structopt(parse(from_os_str))]
+    pub file: PathBuf,
+
+    /// Only run the request with this name
+    ///
+    /// Requests are named by the text after the `###` separator
+    /// or by a `# @name` comment.
+    pub name: Option<String>,
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "screaming_snake_case")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Method{
     HEAD(MethodData),
     GET(MethodData),
@@ -192,8 +1015,17 @@ pub enum Method{
     POST(MethodData),
     PATCH(MethodData),
     DELETE(MethodData),
+    OPTIONS(MethodData),
+    TRACE(MethodData),
+    CONNECT(MethodData),
 }
 
+/// The names of the method subcommands, other methods are given as
+/// `hurl METHOD url` through --method
+const METHOD_SUBCOMMANDS: [&str; 9] = [
+    "HEAD", "GET", "PUT", "POST", "PATCH", "DELETE", "OPTIONS", "TRACE", "CONNECT",
+];
+
 impl Method {
     pub fn data(&self) -> &MethodData{
         use Method::*;
@@ -204,6 +1036,24 @@ impl Method {
             PUT(x) => x,
             PATCH(x) => x,
             DELETE(x) => x,
+            OPTIONS(x) => x,
+            TRACE(x) => x,
+            CONNECT(x) => x,
+        }
+    }
+
+    pub fn data_mut(&mut self) -> &mut MethodData{
+        use Method::*;
+        match self{
+            HEAD(x) => x,
+            GET(x) => x,
+            POST(x) => x,
+            PUT(x) => x,
+            PATCH(x) => x,
+            DELETE(x) => x,
+            OPTIONS(x) => x,
+            TRACE(x) => x,
+            CONNECT(x) => x,
         }
     }
 }
@@ -217,6 +1067,9 @@ impl From<&Method> for reqwest::Method{
             Method::PATCH(_)=> reqwest::Method::PATCH,
             Method::POST(_)=> reqwest::Method::POST,
             Method::DELETE(_)=> reqwest::Method::DELETE,
+            Method::OPTIONS(_)=> reqwest::Method::OPTIONS,
+            Method::TRACE(_)=> reqwest::Method::TRACE,
+            Method::CONNECT(_)=> reqwest::Method::CONNECT,
         }
     }
 }
@@ -238,47 +1091,168 @@ impl From<&Method> for reqwest::Method{
 #[derive(StructOpt, Debug)]
 pub struct MethodData{
     /// the URL to request
-    pub usl: String,
+    ///
+    /// Environment variables can be referenced as {{env.NAME}} or ${NAME}.
+    pub url: String,
 
     /// The header, data , and query parametres to add to the request.
+    ///
+    /// Environment variables can be referenced as {{env.NAME}} or ${NAME}.
 
 #[structopt(parse(try_from_str = parse_param))]
 pub parameters: Vec<Parameter>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Parameter{
     //:
     Header{ key: String, value: String},
+    //: with no value, the header is not sent
+    UnsetHeader{ key: String},
     //=
     Data{ key:String, value:String },
     //:=
     RawJsonData{ key: String, value:String},
     //==
     Query {key: String, value:String},
-    //@
-    FormFile{ key: String, value:String},
+    //=:= the value is JSON, sent compact and URL encoded
+    JsonQuery {key: String, value:String},
+    //@ with optional ;type= and ;filename= modifiers
+    FormFile{ key: String, filename:String, options: PartOptions},
     //=@
-    DataFile{key:String, value:String},
+    DataFile{key:String, filename:String},
+    //=@@
+    Base64DataFile{key:String, filename:String},
     // :=@
-    RawJsonDataFile{  key:String, value:String},
+    RawJsonDataFile{  key:String, filename:String},
+    //:<, ==<, =< and :=< until the command is run
+    Command{ key: String, command: String, target: CommandTarget},
+}
+
+/// What a `key:<command` style parameter becomes once its command is run
+#[derive(Debug, Clone, Copy)]
+pub enum CommandTarget{
+    Header,
+    Query,
+    Data,
+    RawJsonData,
 }
 
 impl Parameter {
     pub fn is_form_file(&self) -> bool{
-        match *self{
-            Parameter::FromFile{..} => true,
+        matches!(self, Parameter::FormFile{..})
+    }
+    pub fn is_data(&self) -> bool{
+        !matches!(
+            self,
+            Parameter::Header{..} | Parameter::UnsetHeader{..} | Parameter::Query{..} | Parameter::JsonQuery{..}
+                | Parameter::Command{target: CommandTarget::Header, ..}
+                | Parameter::Command{target: CommandTarget::Query, ..}
+        )
+    }
+
+    pub fn key(&self) -> &str{
+        use Parameter::*;
+        match self{
+            Header{key, ..} | Data{key, ..} | RawJsonData{key, ..} | Query{key, ..} => key,
+            JsonQuery{key, ..} | UnsetHeader{key} => key,
+            FormFile{key, ..} | DataFile{key, ..} | RawJsonDataFile{key, ..} => key,
+            Base64DataFile{key, ..} | Command{key, ..} => key,
+        }
+    }
+
+    /// Whether this parameter replaces `other` when both are given
+    pub fn overrides(&self, other: &Parameter) -> bool{
+        if self.is_header(self.key()) && other.is_header(self.key()){
+            return true;
+        }
+        if std::mem::discriminant(self) != std::mem::discriminant(other){
+            return false;
+        }
+        match self{
+            Parameter::Header{..} => self.key().eq_ignore_ascii_case(other.key()),
+            _ => self.key() == other.key(),
+        }
+    }
+
+    /// Whether this sets or unsets the header `name`
+    pub fn is_header(&self, name: &str) -> bool{
+        match self{
+            Parameter::Header{key, ..} | Parameter::UnsetHeader{key} => {
+                key.eq_ignore_ascii_case(name)
+            }
             _ => false,
         }
     }
-    pub fn is_data(&self) -> bool{
-        match *self {
-            Parameter::Header { .. } => false,
-            Parameter::Query { .. } => false,
-            _ => true,
+
+    pub fn is_unset_header(&self, name: &str) -> bool{
+        match self{
+            Parameter::UnsetHeader{key} => key.eq_ignore_ascii_case(name),
+            _ => false,
+        }
+    }
+
+    pub fn is_query(&self, name: &str) -> bool{
+        match self{
+            Parameter::Query{key, ..} | Parameter::JsonQuery{key, ..} => key == name,
+            _ => false,
         }
     }
 
+    /// A copy of this parameter with `f` applied to its key and value
+    ///
+    /// Filenames are left alone, so that a value captured into a session
+    /// cannot choose which file is read.
+    pub fn try_map<F>(&self, mut f: F) -> HurlResult<Parameter>
+    where
+        F: FnMut(&str) -> HurlResult<String>,
+    {
+        use Parameter::*;
+        Ok(match self{
+            Header{key, value} => Header{key: f(key)?, value: f(value)?},
+            Data{key, value} => Data{key: f(key)?, value: f(value)?},
+            RawJsonData{key, value} => RawJsonData{key: f(key)?, value: f(value)?},
+            Query{key, value} => Query{key: f(key)?, value: f(value)?},
+            UnsetHeader{key} => UnsetHeader{key: f(key)?},
+            JsonQuery{key, value} => JsonQuery{key: f(key)?, value: f(value)?},
+            FormFile{key, filename, options} => FormFile{
+                key: f(key)?,
+                filename: filename.clone(),
+                options: options.clone(),
+            },
+            DataFile{key, filename} => DataFile{key: f(key)?, filename: filename.clone()},
+            Base64DataFile{key, filename} => {
+                Base64DataFile{key: f(key)?, filename: filename.clone()}
+            }
+            RawJsonDataFile{key, filename} => {
+                RawJsonDataFile{key: f(key)?, filename: filename.clone()}
+            }
+            Command{key, command, target} => {
+                Command{key: f(key)?, command: command.clone(), target: *target}
+            }
+        })
+    }
+
+    /// This parameter with the output of its command for the value, or
+    /// with the command as the value, starting with <, unless `run` is set
+    pub fn run_command(self, run: bool) -> HurlResult<Parameter>{
+        let (key, command, target) = match self{
+            Parameter::Command{key, command, target} => (key, command, target),
+            other => return Ok(other),
+        };
+        let value = if run{
+            source::command_value(&command)?
+        } else {
+            format!("<{}", command)
+        };
+        Ok(match target{
+            CommandTarget::Header => Parameter::Header{key, value},
+            CommandTarget::Query => Parameter::Query{key, value},
+            CommandTarget::Data => Parameter::Data{key, value},
+            CommandTarget::RawJsonData => Parameter::RawJsonData{key, value},
+        })
+    }
+
 }
 
 
@@ -299,15 +1273,33 @@ impl Parameter {
 
 
 
+/// How the part for a `key@filename` parameter is sent in a multipart form
+#[derive(Debug, Clone, Default)]
+pub struct PartOptions{
+    /// From `;type=`, otherwise guessed from the file extension, or
+    /// application/octet-stream for stdin
+    pub content_type: Option<String>,
+    /// From `;filename=`, otherwise the name of the file, or stdin
+    pub file_name: Option<String>,
+}
+
 #[derive(Debug)]
 enum Separator{
     Colon,
-    Equil,
+    Equal,
     At,
     ColonEqual,
     EqualEqual,
     EqualAt,
+    EqualAtAt,
+    EqualColonEqual,
     Snail,
+    ColonAt,
+    ColonLess,
+    EqualEqualAt,
+    EqualEqualLess,
+    EqualLess,
+    ColonEqualLess,
 }
 impl TryFrom<&str> for Separator{
     type Error = ();
@@ -320,7 +1312,15 @@ impl TryFrom<&str> for Separator{
             ":=" => Ok(Separator::ColonEqual),
             "==" => Ok(Separator::EqualEqual),
             "=@" => Ok(Separator::EqualAt),
+            "=@@" => Ok(Separator::EqualAtAt),
+            "=:=" => Ok(Separator::EqualColonEqual),
             ":=@" => Ok(Separator::Snail),
+            ":@" => Ok(Separator::ColonAt),
+            ":<" => Ok(Separator::ColonLess),
+            "==@" => Ok(Separator::EqualEqualAt),
+            "==<" => Ok(Separator::EqualEqualLess),
+            "=<" => Ok(Separator::EqualLess),
+            ":=<" => Ok(Separator::ColonEqualLess),
             _ => Err(()),
         }
     }
@@ -356,7 +1356,11 @@ enum Token<'a>{
     Escape(char),
 }
 
-fn gather_escape<'a>(str: &'a str) -> Vec<Token<'a>>{
+/// Split a parameter into text and the characters escaped with a backslash
+///
+/// `=`, `:`, `@` and `<` are escaped so that they are not taken as part
+/// of a separator, and `\\` for a backslash before one of them.
+fn gather_escape<'a>(src: &'a str) -> Vec<Token<'a>>{
     let mut tokens = Vec::new();
     let mut start = 0;
     let mut end = 0;
@@ -371,7 +1375,7 @@ fn gather_escape<'a>(str: &'a str) -> Vec<Token<'a>>{
         }
         let c = a.unwrap();
         if c !='\\'{
-            end +=1;
+            end += c.len_utf8();
             continue;
         }
         let b = chars.next();
@@ -381,29 +1385,38 @@ fn gather_escape<'a>(str: &'a str) -> Vec<Token<'a>>{
         }
         let c = b.unwrap();
         match c {
-            '\\' | '=' | '@' | ':' => {
+            '\\' | '=' | '@' | ':' | '<' => {
                 if start != end {
-                    tokens.push(Token::Text(&str[start..end]));
+                    tokens.push(Token::Text(&src[start..end]));
                 }
                 tokens.push(Token::Escape(c));
                 end += 2;
                 start = end;
             }
-            _ => end += 2,
+            _ => end += 1 + c.len_utf8(),
         }
     }
 }
 
-fn parse_param(src: &str) -> HurlResult<Parameter>{
+/// Headers the client adds to every request which does not give them,
+/// so they can be replaced but not unset
+const ALWAYS_SENT_HEADERS: [&str; 2] = ["User-Agent", "Accept"];
+
+pub fn parse_param(src: &str) -> HurlResult<Parameter>{
     debug!(" Parsing: {}", src);
-    let separators = [":=@", "=@", "==", ":=", "@", "=", ":"];
+    let separators = [
+        ":=@", ":=<", "=@@", "=:=", "==@", "==<", "=@", "=<", "==", ":=", ":@", ":<", "@", "=", ":",
+    ];
     let tokens = gather_escape(src);
 
     let mut found = Vec::new();
     let mut idx = 0;
+    // Where each token, and the one with the separator, starts in src
+    let mut offset = 0;
+    let mut idx_offset = 0;
     for (i, token) in tokens.iter().enumerate(){
         match token {
-            TOken::Text(s) => {
+            Token::Text(s) => {
                 for sep in separators.iter(){
                     if let Some(n) = s.find(sep){
                         found.push((n,sep));
@@ -411,17 +1424,28 @@ fn parse_param(src: &str) -> HurlResult<Parameter>{
                 }
                 if !found.is_empty(){
                     idx = i;
+                    idx_offset = offset;
                     break;
                 }
+                offset += s.len();
             }
-            Token::Escape(_) => {}
+            // A backslash and an ASCII character
+            Token::Escape(_) => offset += 2,
         }
     }
     if found.is_empty(){
-        return Err(Error::ParameterMissingSeparator(str.to_owned()));
+        // Header; sends the header with an empty value
+        if src.len() > 1 && src.ends_with(';') && !src.ends_with("\\;"){
+            return Ok(Parameter::Header{
+                key: src[..src.len() - 1].to_owned(),
+                value: String::new(),
+            });
+        }
+        return Err(Error::ParameterMissingSeparator(src.to_owned()));
     }
     found.sort_by(|(ai, asep), (bi, bsep)| ai.cmp(bi).then(bsep.len().cmp(&asep.len())));
 let sep  = found.first().unwrap().1;
+let value_start = idx_offset + found.first().unwrap().0 + sep.len();
 
 trace!("Found separator: {}", sep);
 
@@ -430,23 +1454,17 @@ let mut value = String::new();
 for (i, token) in tokens.iter().enumerate(){
     if i < idx {
         match token{
-            Token::Text(s) => key.push_str(&s),
-            Token::Escape(c) => {
-                key.push('\\');
-                key.push(*c);
-            }
+            Token::Text(s) => key.push_str(s),
+            Token::Escape(c) => key.push(*c),
         }
     } else if i > idx {
         match token{
-            Token::Text(s) => value.push_str(&s),
-            Token::&Escape(c) => {
-                value.push('\\');
-                value.push(*c);
-            }
+            Token::Text(s) => value.push_str(s),
+            Token::Escape(c) => value.push(*c),
         }
     } else {
         if let Token::Text(s) = token{
-            let part : Vec<&str> = s.split(2, sep).collect();
+            let parts: Vec<&str> = s.splitn(2, sep).collect();
             let k = parts.first().unwrap();
             let v = parts.last().unwrap();
             key.push_str(k);
@@ -458,24 +1476,455 @@ for (i, token) in tokens.iter().enumerate(){
 }
 if let Ok(separator) = Separator::try_from(*sep) {
     match separator{
-        Separator::At => Ok(Parameter::FormFile{
-            key,
-            filename: value,
-        }),
+        Separator::At => {
+            let (filename, options) = parse_part(src, value_start)?;
+            Ok(Parameter::FormFile{key, filename, options})
+        }
         Separator::Equal => Ok(Parameter::Data{key, value}),
+        Separator::Colon if value.is_empty() => {
+            if ALWAYS_SENT_HEADERS.iter().any(|h|h.eq_ignore_ascii_case(&key)){
+                return Err(Error::HeaderAlwaysSent(key));
+            }
+            Ok(Parameter::UnsetHeader{key})
+        }
         Separator::Colon => Ok(Parameter::Header{key, value}),
         Separator::ColonEqual => Ok(Parameter::RawJsonData{key, value}),
         Separator::EqualEqual => Ok(Parameter::Query{key, value}),
+        Separator::EqualColonEqual => Ok(Parameter::JsonQuery{key, value}),
         Separator::EqualAt => Ok(Parameter::DataFile{
             key,
             filename: value,
         }),
+        Separator::EqualAtAt => Ok(Parameter::Base64DataFile{
+            key,
+            filename: value,
+        }),
         Separator::Snail => Ok(Parameter::RawJsonDataFile{
             key,
             filename: value,
         }),
+        Separator::ColonAt => Ok(Parameter::Header{key, value: source::file_value(&value)?}),
+        Separator::ColonLess => {
+            Ok(Parameter::Command{key, command: value, target: CommandTarget::Header})
+        }
+        Separator::EqualEqualAt => Ok(Parameter::Query{key, value: source::file_value(&value)?}),
+        Separator::EqualEqualLess => {
+            Ok(Parameter::Command{key, command: value, target: CommandTarget::Query})
+        }
+        Separator::EqualLess => {
+            Ok(Parameter::Command{key, command: value, target: CommandTarget::Data})
+        }
+        Separator::ColonEqualLess => {
+            Ok(Parameter::Command{key, command: value, target: CommandTarget::RawJsonData})
+        }
     }
 } else {
     unreachable!();
 }
-}
\ No newline at end of file
+}
+
+/// An upper case method name such as PURGE or VERSION-CONTROL
+fn is_method_token(s: &str) -> bool{
+    s.len() > 1
+        && s.chars().next().is_some_and(|c|c.is_ascii_uppercase())
+        && s.chars().all(|c|c.is_ascii_uppercase() || c == '-' || c == '_')
+}
+
+fn parse_method(s: &str) -> HurlResult<reqwest::Method>{
+    reqwest::Method::from_bytes(s.to_ascii_uppercase().as_bytes())
+        .map_err(|_|Error::InvalidMethod(s.to_owned()))
+}
+
+/// Split the value of `key@file.png;type=image/png;filename=avatar.png`
+/// into the file and its part options
+///
+/// A `;` in the file name can be escaped as `\;`. The value starts at
+/// byte `value_start` of `src`, the whole parameter, and errors point at
+/// the offending modifier in `src`.
+fn parse_part(src: &str, value_start: usize) -> HurlResult<(String, PartOptions)>{
+    let mut segments = split_modifiers(&src[value_start..]).into_iter();
+    let (_, filename) = segments.next().unwrap_or_default();
+    if filename.is_empty(){
+        return Err(Error::InvalidPartModifier{
+            param: src.to_owned(),
+            position: value_start,
+            message: "missing file name, use - to read stdin".to_owned(),
+        });
+    }
+
+    let mut options = PartOptions::default();
+    for (start, segment) in segments{
+        let invalid = |message: String| Error::InvalidPartModifier{
+            param: src.to_owned(),
+            position: value_start + start,
+            message,
+        };
+        let (name, modifier) = match segment.find('='){
+            Some(idx) => (&segment[..idx], &segment[idx + 1..]),
+            None => return Err(invalid(format!("expected type=... or filename=..., found '{}'", segment))),
+        };
+        if modifier.is_empty(){
+            return Err(invalid(format!("{} has no value", name)));
+        }
+        let slot = match name{
+            "type" => {
+                if !is_content_type(modifier){
+                    return Err(invalid(format!("'{}' is not a content type like image/png", modifier)));
+                }
+                &mut options.content_type
+            }
+            "filename" => &mut options.file_name,
+            _ => return Err(invalid(format!("unknown modifier '{}', expected type or filename", name))),
+        };
+        if slot.is_some(){
+            return Err(invalid(format!("{} is given more than once", name)));
+        }
+        *slot = Some(modifier.to_owned());
+    }
+    Ok((filename, options))
+}
+
+/// Split on unescaped `;`, returning where each segment starts
+///
+/// The escapes of `gather_escape` are removed here too, so that the
+/// positions are those in the raw value.
+fn split_modifiers(value: &str) -> Vec<(usize, String)>{
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut start = 0;
+    let mut chars = value.char_indices().peekable();
+    while let Some((i, c)) = chars.next(){
+        match c{
+            '\\' if matches!(chars.peek(), Some((_, '\\' | '=' | '@' | ':' | '<' | ';'))) => {
+                current.push(chars.next().unwrap().1);
+            }
+            ';' => {
+                segments.push((start, std::mem::take(&mut current)));
+                start = i + 1;
+            }
+            c => current.push(c),
+        }
+    }
+    segments.push((start, current));
+    segments
+}
+
+fn is_content_type(s: &str) -> bool{
+    let mut parts = s.split('/');
+    match (parts.next(), parts.next(), parts.next()){
+        (Some(kind), Some(subtype), None) => {
+            !kind.is_empty() && !subtype.is_empty() && !s.chars().any(|c|c.is_whitespace())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn header(src: &str) -> (String, String) {
+        match parse_param(src).unwrap() {
+            Parameter::Header{key, value} => (key, value),
+            other => panic!("not a header: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_separators() {
+        assert!(matches!(parse_param("a=b").unwrap(), Parameter::Data{..}));
+        assert!(matches!(parse_param("a==b").unwrap(), Parameter::Query{..}));
+        assert!(matches!(parse_param("a:=1").unwrap(), Parameter::RawJsonData{..}));
+        assert!(matches!(parse_param("a=:=1").unwrap(), Parameter::JsonQuery{..}));
+        assert!(matches!(parse_param("a=@f").unwrap(), Parameter::DataFile{..}));
+        assert!(matches!(parse_param("a=@@f").unwrap(), Parameter::Base64DataFile{..}));
+        assert!(matches!(parse_param("a:=@f").unwrap(), Parameter::RawJsonDataFile{..}));
+        assert!(matches!(parse_param("a:").unwrap(), Parameter::UnsetHeader{..}));
+        assert!(matches!(parse_param("Accept-Encoding:").unwrap(), Parameter::UnsetHeader{..}));
+        assert!(matches!(parse_param("user-agent:"), Err(Error::HeaderAlwaysSent(_))));
+        assert!(matches!(parse_param("Accept:"), Err(Error::HeaderAlwaysSent(_))));
+        assert_eq!(header("Accept;"), ("Accept".to_owned(), String::new()));
+        assert_eq!(header("Referer:http://x/"), ("Referer".to_owned(), "http://x/".to_owned()));
+        assert_eq!(header("X-Empty;"), ("X-Empty".to_owned(), String::new()));
+    }
+
+    #[test]
+    fn escapes_separators() {
+        assert_eq!(header("X-Tag:\\<b>"), ("X-Tag".to_owned(), "<b>".to_owned()));
+        assert_eq!(header("X\\:Y:\\@z"), ("X:Y".to_owned(), "@z".to_owned()));
+        match parse_param("a=\\:=b").unwrap() {
+            Parameter::Data{key, value} => assert_eq!((key.as_str(), value.as_str()), ("a", ":=b")),
+            other => panic!("not data: {:?}", other),
+        }
+        assert_eq!(header("Name:José"), ("Name".to_owned(), "José".to_owned()));
+    }
+
+    #[test]
+    fn reads_values_from_files_and_commands() {
+        let path = std::env::temp_dir().join("hurl-parse-param-test");
+        std::fs::write(&path, "secret\n").unwrap();
+        let value = header(&format!("Authorization:@{}", path.display())).1;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(value, "secret");
+
+        let run = |src: &str, run: bool| parse_param(src).unwrap().run_command(run).unwrap();
+        assert!(matches!(run("X-Sig:<echo 'a b'", true), Parameter::Header{value, ..} if value == "a b"));
+        assert!(matches!(run("page==<echo 2", true), Parameter::Query{value, ..} if value == "2"));
+        assert!(matches!(run("n=<echo 3", true), Parameter::Data{value, ..} if value == "3"));
+        assert!(matches!(run("ids:=<echo [1]", true), Parameter::RawJsonData{value, ..} if value == "[1]"));
+    }
+
+    #[test]
+    fn runs_commands_only_when_asked() {
+        let command = |src: &str| match parse_param(src).unwrap() {
+            Parameter::Command{key, command, ..} => (key, command),
+            other => panic!("not a command: {:?}", other),
+        };
+        assert_eq!(command("Link:<https://x/2>; rel=next"), ("Link".to_owned(), "https://x/2>; rel=next".to_owned()));
+        assert_eq!(command("q==<3").1, "3");
+        let literal = parse_param("Link:<https://x/2>").unwrap().run_command(false).unwrap();
+        assert!(matches!(literal, Parameter::Header{value, ..} if value == "<https://x/2>"));
+        assert!(parse_param("X-Sig:<").unwrap().run_command(true).is_err());
+        assert!(parse_param("X-Sig:<hurl-no-such-command").unwrap().run_command(true).is_err());
+
+        let mut app = App::from_iter_safe(&["hurl", "http://x", "X-A:<echo a", "b==<echo b"]).unwrap();
+        app.resolve_commands().unwrap();
+        assert!(matches!(&app.parameters[0], Parameter::Header{value, ..} if value == "<echo a"));
+        let mut app = App::from_iter_safe(&["hurl", "--run-commands", "GET", "http://x", "X-A:<echo a"]).unwrap();
+        app.resolve_commands().unwrap();
+        match &app.cmd {
+            Some(Command::Method(m)) => {
+                assert!(matches!(&m.data().parameters[0], Parameter::Header{value, ..} if value == "a"))
+            }
+            other => panic!("not a method: {:?}", other),
+        }
+    }
+
+    fn part(src: &str) -> (String, PartOptions) {
+        match parse_param(src).unwrap() {
+            Parameter::FormFile{filename, options, ..} => (filename, options),
+            other => panic!("not a form file: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_part_modifiers() {
+        let (filename, options) = part("f@a\\;b.png;type=image/png;filename=é\\@.png");
+        assert_eq!(filename, "a;b.png");
+        assert_eq!(options.content_type.as_deref(), Some("image/png"));
+        assert_eq!(options.file_name.as_deref(), Some("é@.png"));
+        assert_eq!(part("f@\\\\;type=text/plain").0, "\\");
+    }
+
+    #[test]
+    fn points_at_bad_part_modifiers() {
+        let error = |src: &str| parse_param(src).unwrap_err().to_string();
+        assert!(error("f@a;éé=\\@").ends_with("f@a;éé=\\@\n      ^"));
+        assert!(error("f\\@x@a;bad").ends_with("f\\@x@a;bad\n         ^"));
+        assert!(error("f@").contains("missing file name"));
+    }
+
+    fn validate(args: &[&str]) -> HurlResult<()> {
+        App::from_iter_safe(args).unwrap().validate()
+    }
+
+    #[test]
+    fn rejects_conflicting_auth() {
+        let conflict = |args: &[&str]| matches!(validate(args), Err(Error::ConflictingOptions(..)));
+        assert!(conflict(&["hurl", "--oauth", "p", "--token", "t", "http://x"]));
+        assert!(conflict(&["hurl", "--oauth", "p", "--auth", "u:p", "http://x"]));
+        assert!(conflict(&["hurl", "--oauth", "p", "--auth-type", "basic", "http://x"]));
+        assert!(conflict(&["hurl", "--sign", "s", "--oauth", "p", "http://x"]));
+        assert!(conflict(&["hurl", "--sign", "s", "--auth-type", "digest", "--auth", "u:p", "http://x"]));
+        assert!(conflict(&["hurl", "--sign", "s", "--auth-type", "aws-sigv4", "http://x"]));
+        assert!(validate(&["hurl", "--sign", "s", "--token", "t", "http://x"]).is_ok());
+    }
+
+    #[test]
+    fn inherits_auth_only_when_none_is_chosen() {
+        let merge = |args: &[&str], profile: config::Profile| {
+            let mut app = App::from_iter_safe(args).unwrap();
+            app.apply_profile(profile);
+            app.inherit_auth(None, Some("global".to_owned()), None, None);
+            app
+        };
+        let oauth = || config::Profile{oauth: Some("p".to_owned()), ..config::Profile::default()};
+
+        let mut app = merge(&["hurl", "http://x"], oauth());
+        assert_eq!((app.oauth.as_deref(), app.token.as_deref()), (Some("p"), None));
+        assert!(app.validate().is_ok());
+        let app = merge(&["hurl", "--token", "t", "http://x"], oauth());
+        assert_eq!((app.oauth.as_deref(), app.token.as_deref()), (None, Some("t")));
+        let mut app = merge(&["hurl", "--sign", "s", "http://x"], oauth());
+        assert!(app.oauth.is_none());
+        assert!(app.validate().is_ok());
+        let mut app = merge(&["hurl", "--auth-type", "digest", "http://x"], oauth());
+        assert!(app.oauth.is_none());
+        assert!(app.validate().is_ok());
+
+        let app = merge(&["hurl", "http://x"], config::Profile::default());
+        assert_eq!(app.token.as_deref(), Some("global"));
+        let sign = config::Profile{sign: Some("s".to_owned()), ..config::Profile::default()};
+        let app = merge(&["hurl", "--oauth", "p", "http://x"], sign);
+        assert!(app.sign.is_none());
+    }
+
+    #[test]
+    fn rejects_method_with_subcommands() {
+        let conflict = |args: &[&str]| matches!(validate(args), Err(Error::ConflictingOptions(..)));
+        assert!(conflict(&["hurl", "-X", "PURGE", "GET", "http://x"]));
+        assert!(conflict(&["hurl", "-X", "PURGE", "run", "requests.http"]));
+        assert!(validate(&["hurl", "-X", "PURGE", "http://x"]).is_ok());
+    }
+
+    #[test]
+    fn takes_only_the_first_positional_for_a_method() {
+        let parse = |args: &[&str]| {
+            let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+            App::parse_with_methods(&args).map(|app| (app.method, app.url))
+        };
+        assert_eq!(
+            parse(&["hurl", "-v", "PROPFIND", "http://x", "a==1"]),
+            Some((Some(reqwest::Method::from_bytes(b"PROPFIND").unwrap()), Some("http://x".to_owned())))
+        );
+        assert_eq!(parse(&["hurl", "http://x", "MKCOL"]), None);
+        assert_eq!(parse(&["hurl", "MKCOL"]), None);
+    }
+
+    #[test]
+    fn overrides_parameters_of_the_same_kind_and_key() {
+        let p = |src: &str| parse_param(src).unwrap();
+        assert!(p("X-A:1").overrides(&p("x-a:2")));
+        assert!(p("X-A:").overrides(&p("X-A:2")));
+        assert!(p("X-A;").overrides(&p("X-A:2")));
+        assert!(p("a=1").overrides(&p("a=2")));
+        assert!(!p("a=1").overrides(&p("a=@f")));
+        assert!(!p("A=1").overrides(&p("a=2")));
+        assert!(!p("a==1").overrides(&p("a=2")));
+        assert!(!p("a=1").overrides(&p("b=1")));
+        assert!(p("a:=1").overrides(&p("a:=[2]")));
+    }
+
+    #[test]
+    fn merges_saved_requests() {
+        let saved: config::SavedRequest = toml::from_str(
+            r#"
+            method = "patch"
+            url = "http://x/users"
+            headers = { X-Token = "saved", X-Kept = "saved" }
+            query = { page = "1" }
+            data = { name = "ann", role = "user" }
+            parameters = ["ids:=[1,2]"]
+            "#,
+        )
+        .unwrap();
+        let mut app = App::from_iter_safe(&["hurl", "@update", "x-token:given", "name=bob", "page==2"]).unwrap();
+        app.saved_requests.insert("update".to_owned(), saved);
+        app.resolve_saved_request().unwrap();
+        assert_eq!(app.url.as_deref(), Some("http://x/users"));
+        assert_eq!(app.saved_method, Some(reqwest::Method::PATCH));
+        let parameters: Vec<String> = app.parameters.iter().map(|p| format!("{:?}", p)).collect();
+        assert_eq!(
+            parameters,
+            [
+                r#"Header { key: "X-Kept", value: "saved" }"#,
+                r#"Data { key: "role", value: "user" }"#,
+                r#"RawJsonData { key: "ids", value: "[1,2]" }"#,
+                r#"Header { key: "x-token", value: "given" }"#,
+                r#"Data { key: "name", value: "bob" }"#,
+                r#"Query { key: "page", value: "2" }"#,
+            ]
+        );
+
+        let mut app = App::from_iter_safe(&["hurl", "@missing"]).unwrap();
+        assert!(matches!(app.resolve_saved_request(), Err(Error::UnknownSavedRequest(_))));
+        let mut app = App::from_iter_safe(&["hurl", "http://x"]).unwrap();
+        app.resolve_saved_request().unwrap();
+        assert_eq!(app.url.as_deref(), Some("http://x"));
+    }
+
+    fn load_query_file(name: &str, content: &str) -> HurlResult<Vec<String>> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, content).unwrap();
+        let mut app = App::from_iter_safe(&["hurl", "http://x"]).unwrap();
+        app.query_file = Some(path.clone());
+        let result = app.load_query_file();
+        std::fs::remove_file(&path).unwrap();
+        result?;
+        Ok(app.query_file_parameters.iter().map(|p| format!("{:?}", p)).collect())
+    }
+
+    #[test]
+    fn loads_query_files() {
+        let expected = [
+            r#"JsonQuery { key: "filter", value: "{\"a\":1}" }"#,
+            r#"Query { key: "flag", value: "true" }"#,
+            r#"Query { key: "n", value: "2" }"#,
+            r#"Query { key: "tag", value: "a" }"#,
+            r#"Query { key: "tag", value: "b" }"#,
+        ];
+        let json = r#"{"flag": true, "filter": {"a": 1}, "n": 2, "tag": ["a", "b"]}"#;
+        assert_eq!(load_query_file("hurl-query.json", json).unwrap(), expected);
+        let toml = "flag = true\nn = 2\ntag = [\"a\", \"b\"]\n[filter]\na = 1\n";
+        assert_eq!(load_query_file("hurl-query.toml", toml).unwrap(), expected);
+
+        for (name, content) in &[
+            ("hurl-query-null.json", r#"{"a": null}"#),
+            ("hurl-query-list.json", "[1, 2]"),
+            ("hurl-query-bad.toml", "a = "),
+        ] {
+            assert!(matches!(load_query_file(name, content), Err(Error::InvalidQueryFile(..))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn finds_the_env_file_before_parsing() {
+        let find = |args: &[&str]| {
+            let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+            App::env_file_arg(&args)
+        };
+        assert_eq!(find(&["hurl", "--env-file", "a.env", "http://x"]), Some(PathBuf::from("a.env")));
+        assert_eq!(find(&["hurl", "http://x", "--env-file=b.env"]), Some(PathBuf::from("b.env")));
+        assert_eq!(find(&["hurl", "http://x", "--", "--env-file", "c.env"]), None);
+        assert_eq!(find(&["hurl", "--env-file"]), None);
+        assert_eq!(find(&["hurl", "http://x"]), None);
+    }
+
+    #[test]
+    fn requires_a_session_to_capture() {
+        let captures = |args: &[&str]| matches!(validate(args), Err(Error::CaptureWithoutSession));
+        assert!(captures(&["hurl", "--capture", "id=.id", "http://x"]));
+        assert!(!captures(&["hurl", "--capture", "id=.id", "--session", "s", "http://x"]));
+    }
+
+    #[test]
+    fn profile_headers_take_precedence_in_order() {
+        let mut app = App::from_iter_safe(&["hurl", "http://x"]).unwrap();
+        let headers = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs.iter().map(|(k, v)|(k.to_string(), v.to_string())).collect()
+        };
+        app.apply_profile(config::Profile{
+            headers: headers(&[("X-B", "profile"), ("X-A", "profile")]),
+            ..config::Profile::default()
+        });
+        app.config_defaults.fill_from(config::Defaults{
+            headers: headers(&[("x-a", "global"), ("X-C", "global")]),
+            query: BTreeMap::new(),
+        });
+        let headers: Vec<(&str, &str)> = app
+            .config_defaults
+            .headers
+            .iter()
+            .map(|(k, v)|(k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(headers, [("X-A", "profile"), ("X-B", "profile"), ("X-C", "global")]);
+    }
+
+    #[test]
+    fn does_not_map_filenames() {
+        let param = parse_param("a=@{{file}}").unwrap();
+        let mapped = param.try_map(|s|Ok(s.replace("{{file}}", "/etc/passwd"))).unwrap();
+        assert!(matches!(mapped, Parameter::DataFile{filename, ..} if filename == "{{file}}"));
+    }
+}
@@ -0,0 +1,285 @@
+use crate::app::App;
+use crate::errors::{Error, HurlResult};
+use log::trace;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::fmt;
+
+/// A single expectation which did not hold for a response
+#[derive(Debug)]
+pub struct Failure{
+    pub what: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Failure{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        writeln!(f, "{}", self.what)?;
+        writeln!(f, "  - expected: {}", self.expected)?;
+        write!(f, "  + actual:   {}", self.actual)
+    }
+}
+
+pub fn has_expectations(app: &App) -> bool{
+    app.expect_status.is_some()
+        || !app.expect_header.is_empty()
+        || !app.expect_json.is_empty()
+        || !app.expect_body_contains.is_empty()
+}
+
+/// Check the response against every `--expect-*` option
+///
+/// All expectations are checked so that every failure is reported at once.
+pub fn check(app: &App, status: StatusCode, headers: &HeaderMap, body: &str) -> HurlResult<()>{
+    let mut failures = Vec::new();
+
+    if let Some(expected) = app.expect_status{
+        trace!("Checking status {}", expected);
+        if status.as_u16() != expected{
+            failures.push(Failure{
+                what: "status".to_owned(),
+                expected: expected.to_string(),
+                actual: status.as_u16().to_string(),
+            });
+        }
+    }
+
+    for expected in app.expect_header.iter(){
+        trace!("Checking header {}", expected);
+        failures.extend(check_header(expected, headers));
+    }
+
+    if !app.expect_json.is_empty(){
+        match serde_json::from_str::<Value>(body){
+            Ok(json) => {
+                for expected in app.expect_json.iter(){
+                    trace!("Checking JSON {}", expected);
+                    failures.extend(check_json(expected, &json));
+                }
+            }
+            Err(_) => failures.push(Failure{
+                what: "JSON body".to_owned(),
+                expected: "a JSON document".to_owned(),
+                actual: summarize(body),
+            }),
+        }
+    }
+
+    for expected in app.expect_body_contains.iter(){
+        trace!("Checking body contains {}", expected);
+        if !body.contains(expected.as_str()){
+            failures.push(Failure{
+                what: "body contains".to_owned(),
+                expected: expected.clone(),
+                actual: summarize(body),
+            });
+        }
+    }
+
+    if failures.is_empty(){
+        Ok(())
+    } else {
+        Err(Error::AssertionFailed(failures))
+    }
+}
+
+fn check_header(expected: &str, headers: &HeaderMap) -> Option<Failure>{
+    let (name, value) = match expected.find(':'){
+        Some(idx) => {
+            let (name, value) = expected.split_at(idx);
+            (name.trim(), Some(value[1..].trim()))
+        }
+        None => (expected.trim(), None),
+    };
+    let actual: Vec<&str> = headers
+        .get_all(name)
+        .iter()
+        .map(|v| v.to_str().unwrap_or("BAD HEADER VALUE"))
+        .collect();
+    let matches = match value{
+        Some(value) => actual.iter().any(|a|header_matches(value, a)),
+        None => !actual.is_empty(),
+    };
+    if matches{
+        return None;
+    }
+    Some(Failure{
+        what: format!("header {}", name),
+        expected: value.unwrap_or("<present>").to_owned(),
+        actual: if actual.is_empty(){
+            "<missing>".to_owned()
+        } else {
+            actual.join(", ")
+        },
+    })
+}
+
+/// Whether the header value `actual` is the `expected` one
+///
+/// Values without a `;` also match a value with parameters by its media
+/// type, ignoring case, so `application/json` matches
+/// `application/json; charset=utf-8`. An expected value ending in `*`
+/// matches any value starting with the rest of it.
+fn header_matches(expected: &str, actual: &str) -> bool{
+    if let Some(prefix) = expected.strip_suffix('*'){
+        return actual.starts_with(prefix);
+    }
+    if actual == expected{
+        return true;
+    }
+    let media_type = actual.split(';').next().unwrap_or("").trim();
+    !expected.contains(';') && media_type.eq_ignore_ascii_case(expected)
+}
+
+/// Check an expression such as `.data.id == 5`, `.items[0].name != "x"`
+/// or just `.data.id` for existence
+fn check_json(expr: &str, json: &Value) -> Option<Failure>{
+    let (path, op, expected) = match find_operator(expr){
+        Some(idx) => (&expr[..idx], &expr[idx..idx + 2], Some(expr[idx + 2..].trim())),
+        None => (expr, "", None),
+    };
+    let path = path.trim();
+    let actual = lookup(json, path);
+
+    let expected = match expected{
+        Some(e) => e,
+        None if actual.is_some() => return None,
+        None => {
+            return Some(Failure{
+                what: format!("JSON {}", path),
+                expected: "<present>".to_owned(),
+                actual: "<missing>".to_owned(),
+            })
+        }
+    };
+    // Unquoted values which are not JSON are compared as strings
+    let expected_value: Value =
+        serde_json::from_str(expected).unwrap_or_else(|_| Value::String(expected.to_owned()));
+    let equal = actual.map(|a| *a == expected_value).unwrap_or(false);
+    let holds = if op == "==" { equal } else { !equal };
+    if holds{
+        return None;
+    }
+    Some(Failure{
+        what: format!("JSON {} {}", path, op),
+        expected: expected_value.to_string(),
+        actual: actual
+            .map(|a| a.to_string())
+            .unwrap_or_else(|| "<missing>".to_owned()),
+    })
+}
+
+/// The index of the first `==` or `!=` outside a quoted string, so that
+/// an expected value such as `"a==b"` is not split
+fn find_operator(expr: &str) -> Option<usize>{
+    let bytes = expr.as_bytes();
+    let mut in_quotes = false;
+    let mut i = 0;
+    while i + 1 < bytes.len(){
+        match bytes[i]{
+            b'\\' if in_quotes => i += 1,
+            b'"' => in_quotes = !in_quotes,
+            b'=' | b'!' if !in_quotes && bytes[i + 1] == b'=' => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Look up a value with a jq-like path such as `.data.items[0].id`
+///
+/// The path `.` refers to the whole document.
+pub fn lookup<'a>(json: &'a Value, path: &str) -> Option<&'a Value>{
+    let mut current = json;
+    let path = path.trim().trim_start_matches('.');
+    if path.is_empty(){
+        return Some(current);
+    }
+    for segment in path.split('.'){
+        let (key, indexes) = match segment.find('['){
+            Some(idx) => segment.split_at(idx),
+            None => (segment, ""),
+        };
+        if !key.is_empty(){
+            current = current.get(key)?;
+        }
+        for index in indexes.split('[').filter(|i| !i.is_empty()){
+            let i: usize = index.trim_end_matches(']').parse().ok()?;
+            current = current.get(i)?;
+        }
+    }
+    Some(current)
+}
+
+fn summarize(body: &str) -> String{
+    const MAX: usize = 200;
+    if body.chars().count() <= MAX{
+        return body.to_owned();
+    }
+    let short: String = body.chars().take(MAX).collect();
+    format!("{}...", short)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use serde_json::json;
+
+    #[test]
+    fn matches_header_values() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("application/json; charset=utf-8"));
+        headers.insert("location", HeaderValue::from_static("https://x/items/42"));
+        headers.append("vary", HeaderValue::from_static("Accept"));
+        headers.append("vary", HeaderValue::from_static("Origin"));
+        for expected in &[
+            "Content-Type",
+            "content-type: application/json; charset=utf-8",
+            "Content-Type: application/json",
+            "Content-Type: Application/JSON",
+            "Content-Type: application/*",
+            "Location: https://x/items/*",
+            "Location: https://x/items/42",
+            "Vary: Origin",
+        ] {
+            assert!(check_header(expected, &headers).is_none(), "{}", expected);
+        }
+        for expected in &[
+            "X-Missing",
+            "Content-Type: application/xml",
+            "Content-Type: application/json; charset=latin1",
+            "Content-Type: application",
+            "Location: https://x/items",
+            "Location: https://y/*",
+        ] {
+            assert!(check_header(expected, &headers).is_some(), "{}", expected);
+        }
+        let failure = check_header("Vary: Cookie", &headers).unwrap();
+        assert_eq!((failure.expected.as_str(), failure.actual.as_str()), ("Cookie", "Accept, Origin"));
+    }
+
+    #[test]
+    fn compares_json_values() {
+        let json = json!({"data": {"id": 5, "items": [{"name": "x"}]}});
+        assert!(check_json(".data.id == 5", &json).is_none());
+        assert!(check_json(".data.items[0].name != \"y\"", &json).is_none());
+        assert!(check_json(".data.id", &json).is_none());
+        assert!(check_json(".data.missing", &json).is_some());
+        assert!(check_json(".data.id == 6", &json).is_some());
+    }
+
+    #[test]
+    fn ignores_operators_in_quoted_values() {
+        let json = json!({"q": "a==b", "r": "c!=d", "s": "e\"==f"});
+        assert!(check_json(".q == \"a==b\"", &json).is_none());
+        assert!(check_json(".q != \"a\"", &json).is_none());
+        assert!(check_json(".r == \"c!=d\"", &json).is_none());
+        assert!(check_json(".s == \"e\\\"==f\"", &json).is_none());
+        let failure = check_json(".q != \"a==b\"", &json).unwrap();
+        assert_eq!(failure.what, "JSON .q !=");
+    }
+}
@@ -0,0 +1,304 @@
+use crate::crypto::{hex, hmac_sha256, insert};
+use crate::errors::{Error, HurlResult};
+use chrono::{DateTime, Utc};
+use log::trace;
+use percent_encoding::percent_decode_str;
+use reqwest::Request;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+#[derive(Debug)]
+pub struct Credentials{
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+/// Read credentials from the standard environment variables, or else
+/// from the `AWS_PROFILE` (or `default`) section of ~/.aws/credentials
+pub fn credentials() -> HurlResult<Credentials>{
+    if let (Ok(access_key_id), Ok(secret_access_key)) = (
+        env::var("AWS_ACCESS_KEY_ID"),
+        env::var("AWS_SECRET_ACCESS_KEY"),
+    ){
+        trace!("Using AWS credentials from the environment");
+        return Ok(Credentials{
+            access_key_id,
+            secret_access_key,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        });
+    }
+
+    let path = env::var_os("AWS_SHARED_CREDENTIALS_FILE")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|d| d.join(".aws").join("credentials")))
+        .ok_or(Error::AwsCredentialsMissing)?;
+    let profile = env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_owned());
+    let content = fs::read_to_string(&path).map_err(|_| Error::AwsCredentialsMissing)?;
+    trace!("Using AWS profile {} from {}", profile, path.display());
+
+    let mut in_profile = false;
+    let mut access_key_id = None;
+    let mut secret_access_key = None;
+    let mut session_token = None;
+    for line in content.lines(){
+        let line = line.trim();
+        if line.starts_with('[') && line.ends_with(']'){
+            in_profile = line[1..line.len() - 1].trim() == profile;
+            continue;
+        }
+        if !in_profile{
+            continue;
+        }
+        if let Some(idx) = line.find('='){
+            let value = line[idx + 1..].trim().to_owned();
+            match line[..idx].trim(){
+                "aws_access_key_id" => access_key_id = Some(value),
+                "aws_secret_access_key" => secret_access_key = Some(value),
+                "aws_session_token" => session_token = Some(value),
+                _ => {}
+            }
+        }
+    }
+    match (access_key_id, secret_access_key){
+        (Some(access_key_id), Some(secret_access_key)) => Ok(Credentials{
+            access_key_id,
+            secret_access_key,
+            session_token,
+        }),
+        _ => Err(Error::AwsCredentialsMissing),
+    }
+}
+
+/// Sign a request with AWS Signature Version 4
+///
+/// This must be the last change made to the request. `payload_sha256` is
+/// the hash of the body, which reqwest does not give back.
+pub fn sign(
+    request: &mut Request,
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    payload_sha256: &[u8],
+) -> HurlResult<()>{
+    sign_at(request, credentials, region, service, payload_sha256, Utc::now())
+}
+
+fn sign_at(
+    request: &mut Request,
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    payload_sha256: &[u8],
+    now: DateTime<Utc>,
+) -> HurlResult<()>{
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload_hash = hex(payload_sha256);
+
+    insert(request, "x-amz-date", &amz_date)?;
+    // Only S3 needs the payload hash as a header
+    if service == "s3"{
+        insert(request, "x-amz-content-sha256", &payload_hash)?;
+    }
+    if let Some(token) = &credentials.session_token{
+        insert(request, "x-amz-security-token", token)?;
+    }
+
+    let url = request.url();
+    let host = match url.port(){
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or(""), port),
+        None => url.host_str().unwrap_or("").to_owned(),
+    };
+    // Values of a header given more than once are joined with commas
+    let mut headers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (name, value) in request.headers().iter(){
+        if !name.as_str().starts_with("x-amz-") && name.as_str() != "content-type"{
+            continue;
+        }
+        let value = value.to_str().unwrap_or("");
+        let value = value.split_whitespace().collect::<Vec<&str>>().join(" ");
+        headers.entry(name.as_str().to_owned()).or_default().push(value);
+    }
+    headers.insert("host".to_owned(), vec![host]);
+    let signed_headers = headers
+        .keys()
+        .map(|name| name.as_str())
+        .collect::<Vec<&str>>()
+        .join(";");
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, values)| format!("{}:{}\n", name, values.join(",")))
+        .collect();
+
+    // Path segments are encoded once for S3 and twice for other services
+    let path = if url.path().is_empty() { "/" } else { url.path() };
+    let canonical_uri = path
+        .split('/')
+        .map(|segment| {
+            let segment = uri_encode(&decode(segment));
+            if service == "s3"{
+                segment
+            } else {
+                uri_encode(segment.as_bytes())
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("/");
+    // The query is decoded without turning + into a space
+    let mut query: Vec<(String, String)> = url
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (uri_encode(&decode(k)), uri_encode(&decode(v)))
+        })
+        .collect();
+    query.sort();
+    let canonical_query = query
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<String>>()
+        .join("&");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method().as_str(),
+        canonical_uri,
+        canonical_query,
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+    trace!("AWS canonical request:\n{}", canonical_request);
+
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM,
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = hmac_sha256(
+        format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+        date.as_bytes(),
+    );
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    let key = hmac_sha256(&key, b"aws4_request");
+    let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+    let authorization = format!(
+        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+        ALGORITHM, credentials.access_key_id, scope, signed_headers, signature
+    );
+    insert(request, "authorization", &authorization)
+}
+
+fn decode(s: &str) -> Vec<u8>{
+    percent_decode_str(s).collect()
+}
+
+/// Percent encode everything but the RFC 3986 unreserved characters
+fn uri_encode(s: &[u8]) -> String{
+    let mut encoded = String::with_capacity(s.len());
+    for &b in s{
+        match b{
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use reqwest::header::{HeaderValue, AUTHORIZATION};
+    use reqwest::{Method, Url};
+
+    // From the AWS Signature Version 4 test suite
+    fn sign_example(method: Method, url: &str, service: &str) -> String {
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE".to_owned(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+            session_token: None,
+        };
+        let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+        let mut request = Request::new(method, Url::parse(url).unwrap());
+        let payload_sha256 = Sha256::digest(b"");
+        sign_at(&mut request, &credentials, "us-east-1", service, &payload_sha256, now).unwrap();
+        request.headers()[AUTHORIZATION].to_str().unwrap().to_owned()
+    }
+
+    fn signature(authorization: &str) -> &str {
+        authorization.rsplit("Signature=").next().unwrap()
+    }
+
+    #[test]
+    fn signs_get_vanilla() {
+        let authorization = sign_example(Method::GET, "https://example.amazonaws.com/", "service");
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn signs_get_vanilla_query_order() {
+        let authorization = sign_example(
+            Method::GET,
+            "https://example.amazonaws.com/?Param2=value2&Param1=value1",
+            "service",
+        );
+        assert_eq!(
+            signature(&authorization),
+            "b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"
+        );
+    }
+
+    #[test]
+    fn signs_get_vanilla_empty_query_key() {
+        let authorization = sign_example(Method::GET, "https://example.amazonaws.com/?Param1=value1", "service");
+        assert_eq!(
+            signature(&authorization),
+            "a67d582fa61cc504c4bae71f336f98b97f1ea3c7a6bfe1b6e45aec72011b9aeb"
+        );
+    }
+
+    #[test]
+    fn encodes_paths_and_queries() {
+        assert_eq!(uri_encode(&decode("a%20b!'()*")), "a%20b%21%27%28%29%2A");
+        assert_eq!(uri_encode(&decode("a+b")), "a%2Bb");
+    }
+
+    #[test]
+    fn joins_repeated_headers() {
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE".to_owned(),
+            secret_access_key: "secret".to_owned(),
+            session_token: None,
+        };
+        let mut request = Request::new(Method::GET, Url::parse("https://example.amazonaws.com/").unwrap());
+        request.headers_mut().append("x-amz-meta", HeaderValue::from_static("a"));
+        request.headers_mut().append("x-amz-meta", HeaderValue::from_static("b"));
+        let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+        sign_at(&mut request, &credentials, "us-east-1", "service", &Sha256::digest(b""), now).unwrap();
+        let authorization = request.headers()[AUTHORIZATION].to_str().unwrap();
+        assert!(authorization.contains("SignedHeaders=host;x-amz-date;x-amz-meta,"));
+    }
+}
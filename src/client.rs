@@ -1,18 +1,42 @@
-use crate::app::{App, Mrthod, Parameter};
+use crate::app::{App, AuthType, Method, Parameter, PartOptions, QueryArray};
+use crate::aws;
+use crate::config;
+use crate::credentials;
+use crate::digest;
+use crate::jwt;
+use crate::netrc;
+use crate::oauth;
+use crate::payload::{Form, Part, Payload};
 use crate::errors::{Error, HurlResult};
 use log::{info, debug, trace, log_enabled, self};
 use crate::session::Session;
-use reqwest::multipart::Form;
-use reqwest::{Client, RequestBuilder, Response, Url, Method, Request};
+use crate::signing;
+use crate::source;
+use crate::variables;
+use reqwest::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use reqwest::{Client, ClientBuilder, RequestBuilder, Response, StatusCode, Url, Request};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
+use std::path::Path;
 use std::time::Instant;
 
+/// WebDAV methods whose request bodies are XML documents
+const XML_BODY_METHODS: [&str; 7] = [
+    "PROPFIND",
+    "PROPPATCH",
+    "REPORT",
+    "LOCK",
+    "SEARCH",
+    "MKCALENDAR",
+    "ACL",
+];
+const XML_CONTENT_TYPE: &str = "application/xml; charset=utf-8";
+
 pub fn perform_method(
     app: &App,
-    mrthod: &Method,
+    method: &Method,
     session: &mut Option<Session>,
 ) -> HurlResult<Response>{
     let method_data = method.data();
@@ -27,14 +51,51 @@ pub fn perform_method(
 
 pub fn perform(
     app: &App,
-    meyhod: reqwest::Method,
+    method: reqwest::Method,
     session: &mut Option<Session>,
     raw_url: &str,
-    parametrers: &Vec<Parameter>,
+    parameters: &[Parameter],
 ) -> HurlResult<Response>{
-    let client = Client::new();
-    let url = parse(app, raw_url)?;
+    perform_with_body(app, method, session, raw_url, parameters, None)
+}
+
+/// Like `perform` but with an optional raw body, as given in a request file
+pub fn perform_with_body(
+    app: &App,
+    method: reqwest::Method,
+    session: &mut Option<Session>,
+    raw_url: &str,
+    parameters: &[Parameter],
+    body: Option<String>,
+) -> HurlResult<Response>{
+    let vars = session
+        .as_ref()
+        .map(|s|s.variables().clone())
+        .unwrap_or_default();
+    let interpolate = |s: &str| variables::interpolate(s, &vars, app.strict);
+    let raw_url = interpolate(raw_url)?;
+    let parameters = app
+        .query_file_parameters
+        .iter()
+        .filter(|q|!parameters.iter().any(|p|p.is_query(q.key())))
+        .chain(parameters.iter())
+        .map(|p|p.try_map(interpolate))
+        .collect::<HurlResult<Vec<Parameter>>>()?;
+    let parameters = &parameters;
+    let body = match body{
+        Some(b) => Some(interpolate(&b)?),
+        None => None,
+    };
+
+    let mut client_builder = client_builder(app)?;
+    if parameters.iter().any(|p|p.is_unset_header("accept-encoding")){
+        client_builder = client_builder.gzip(false);
+    }
+    let client = client_builder.build()?;
+    let url = parse(app, &raw_url)?;
     debug!(" Parsed url: {} ", url);
+    let host = url.host_str().unwrap_or("").to_owned();
+    let defaults = config::defaults_for_host(app, &host);
 
     let is_multipart = parameters.iter().any(|p|p.is_form_file());
     if is_multipart{
@@ -43,58 +104,419 @@ pub fn perform(
             return Err(Error::NotFromButHasFormFile);
         }
     }
+    let has_content_type = sets_header("content-type", parameters, session, &defaults);
+    let is_xml_method = XML_BODY_METHODS.contains(&method.as_str());
+    let is_propfind = method.as_str() == "PROPFIND";
+    if method == reqwest::Method::TRACE
+        && (body.is_some() || app.upload.is_some() || parameters.iter().any(|p|p.is_data()))
+    {
+        return Err(Error::BodyNotAllowed(method.to_string()));
+    }
     let mut builder = client.request(method, url);
+    if is_propfind && !sets_header("depth", parameters, session, &defaults){
+        trace!("Defaulting PROPFIND to Depth: 1");
+        builder = builder.header("Depth", "1");
+    }
 
     builder = handel_session(
         builder,
         session,
-        parametrers,
+        parameters,
         !app.read_only,
         &app.auth,
         &app.token,
     );
-        builder = handle_parameters(builder, app.form, is_multipart, parametrers)?;
-        builder = handle_auth(builder, &app.auth, &app.token)?;
-        if log_enabled!(log::Level::Info){
-            let start = Instant::now();
-            let result = buildr.send().map_err(From::from);
-            let elapsed = start.elapsed();
-            info!("Elapsed time: {:?}", elapsed);
-            result} else{
-                builder.send().map_err(From::from)
+        builder = handle_config_defaults(builder, defaults, session, parameters);
+        let (b, mut payload) = handle_parameters(
+            builder,
+            app.form,
+            is_multipart,
+            app.query_array,
+            parameters,
+            has_content_type,
+        )?;
+        builder = b;
+        if let Some(body) = body{
+            trace!("Adding raw body");
+            if is_xml_method && !has_content_type{
+                builder = builder.header(CONTENT_TYPE, XML_CONTENT_TYPE);
+            }
+            payload = Some(Payload::Bytes(body.into_bytes()));
+        }
+        if let Some(path) = &app.upload{
+            if payload.is_some(){
+                return Err(Error::BodyWithDataParameters);
             }
+            if !has_content_type{
+                let content_type = if is_xml_method{
+                    XML_CONTENT_TYPE
+                } else {
+                    "application/octet-stream"
+                };
+                builder = builder.header(CONTENT_TYPE, content_type);
+            }
+            payload = Some(Payload::upload(path, needs_replay(app))?);
+        }
+        if let Some(payload) = &payload{
+            builder = builder.body(payload.body(!app.quiet)?);
+        }
+        builder = handle_auth(builder, &app.auth, &app.token, app.auth_type, app.show_token_claims)?;
+        if app.auth_type.is_none() || app.auth_type == Some(AuthType::Basic){
+            builder = handle_netrc(builder, app, &host, session)?;
+        }
+        let mut request = builder.build()?;
+        remove_unset_headers(&mut request, parameters);
+        let payload = payload.as_ref();
+        if app.auth_type == Some(AuthType::Digest){
+            return perform_digest(app, &client, request, payload, &host, session);
+        }
+        if let Some(provider) = &app.oauth{
+            return perform_oauth(app, &client, request, payload, provider, session);
+        }
+        if app.auth_type == Some(AuthType::AwsSigV4){
+            sign_aws(app, &mut request, payload)?;
+        }
+        execute(app, &client, request, payload)
     }
 
+/// Whether the body is hashed for authentication or may be sent again,
+/// so that stdin must be read into memory
+fn needs_replay(app: &App) -> bool{
+    app.oauth.is_some()
+        || app.sign.is_some()
+        || app.auth_type == Some(AuthType::Digest)
+        || app.auth_type == Some(AuthType::AwsSigV4)
+}
+
+/// A copy of `request` with a new body, as a body can only be sent once
+fn copy_request(app: &App, request: &Request, payload: Option<&Payload>) -> HurlResult<Request>{
+    let mut copy = Request::new(request.method().clone(), request.url().clone());
+    *copy.headers_mut() = request.headers().clone();
+    if let Some(payload) = payload{
+        *copy.body_mut() = Some(payload.body(!app.quiet)?);
+    }
+    Ok(copy)
+}
+
+fn sign_aws(app: &App, request: &mut Request, payload: Option<&Payload>) -> HurlResult<()>{
+    let region = app
+        .aws_region
+        .clone()
+        .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+        .ok_or(Error::AwsOptionMissing("--aws-region"))?;
+    let service = app
+        .aws_service
+        .as_ref()
+        .ok_or(Error::AwsOptionMissing("--aws-service"))?;
+    trace!("Signing request for AWS {} in {}", service, region);
+    let payload_hash = match payload{
+        Some(payload) => payload.sha256()?,
+        None => Sha256::digest(b"").to_vec(),
+    };
+    aws::sign(request, &aws::credentials()?, &region, service, &payload_hash)
+}
+
+/// Send a complete request, signing it first if a --sign scheme is used
+fn execute(
+    app: &App,
+    client: &Client,
+    mut request: Request,
+    payload: Option<&Payload>,
+) -> HurlResult<Response>{
+    if let Some(name) = &app.sign{
+        let scheme = app
+            .signing_schemes
+            .get(name)
+            .ok_or_else(|| Error::UnknownSigningScheme(name.clone()))?;
+        trace!("Signing request with {}", name);
+        signing::sign(&mut request, scheme, payload)?;
+    }
+    if log_enabled!(log::Level::Info){
+        let start = Instant::now();
+        let result = client.execute(request).map_err(From::from);
+        let elapsed = start.elapsed();
+        info!("Elapsed time: {:?}", elapsed);
+        result
+    } else {
+        client.execute(request).map_err(From::from)
+    }
+}
+
+/// Send a request with a bearer token from an OAuth2 provider
+///
+/// If the token is rejected it is refreshed and the request sent again.
+fn perform_oauth(
+    app: &App,
+    client: &Client,
+    mut request: Request,
+    payload: Option<&Payload>,
+    provider: &str,
+    session: &mut Option<Session>,
+) -> HurlResult<Response>{
+    let token = oauth::access_token(app, client, provider, session, false)?;
+    let mut retry = copy_request(app, &request, payload)?;
+    set_bearer(&mut request, &token)?;
+    let resp = execute(app, client, request, payload)?;
+    if resp.status() != StatusCode::UNAUTHORIZED{
+        return Ok(resp);
+    }
+    trace!("OAuth token rejected, refreshing");
+    let token = oauth::access_token(app, client, provider, session, true)?;
+    set_bearer(&mut retry, &token)?;
+    execute(app, client, retry, payload)
+}
+
+fn set_bearer(request: &mut Request, token: &str) -> HurlResult<()>{
+    let header = format!("Bearer {}", token);
+    let value = HeaderValue::from_str(&header).map_err(|_|Error::InvalidHeaderValue(header.clone()))?;
+    request.headers_mut().insert(AUTHORIZATION, value);
+    Ok(())
+}
+
+/// Send a request with Digest authentication
+///
+/// A challenge stored in the session for the host is answered straight
+/// away with the next `nc`. Otherwise, or if the server rejects it as
+/// stale, the request is sent again answering the challenge of the 401.
+fn perform_digest(
+    app: &App,
+    client: &Client,
+    request: Request,
+    payload: Option<&Payload>,
+    host: &str,
+    session: &mut Option<Session>,
+) -> HurlResult<Response>{
+    let (username, password) = digest_credentials(app, host)?;
+    let retry = copy_request(app, &request, payload)?;
+
+    let cached = session.as_ref().and_then(|s|s.digest_challenge(host)).cloned();
+    let (resp, used) = match cached{
+        Some(mut challenge) => {
+            trace!("Reusing digest challenge for {}", host);
+            challenge.nc += 1;
+            let resp = execute_digest(app, client, request, payload, &challenge, &username, &password)?;
+            (resp, Some(challenge))
+        }
+        None => (execute(app, client, request, payload)?, None),
+    };
+    if resp.status() != StatusCode::UNAUTHORIZED{
+        if let (Some(s), Some(challenge)) = (session.as_mut(), used){
+            s.set_digest_challenge(host, challenge);
+        }
+        return Ok(resp);
+    }
+
+    let mut challenge = resp
+        .headers()
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .filter_map(|v|v.to_str().ok())
+        .filter_map(digest::parse_challenge)
+        .next()
+        .ok_or(Error::DigestChallengeMissing)?;
+    trace!("Answering digest challenge for realm {}", challenge.realm);
+    challenge.nc = 1;
+    let resp = execute_digest(app, client, retry, payload, &challenge, &username, &password)?;
+    if let Some(s) = session{
+        s.set_digest_challenge(host, challenge);
+    }
+    Ok(resp)
+}
+
+fn execute_digest(
+    app: &App,
+    client: &Client,
+    mut request: Request,
+    payload: Option<&Payload>,
+    challenge: &digest::Challenge,
+    username: &str,
+    password: &str,
+) -> HurlResult<Response>{
+    let uri = match request.url().query(){
+        Some(query) => format!("{}?{}", request.url().path(), query),
+        None => request.url().path().to_owned(),
+    };
+    let body = match payload{
+        Some(payload) if challenge.needs_body() => Some(payload.bytes()?),
+        Some(_) => None,
+        None => Some(Cow::Borrowed(&b""[..])),
+    };
+    let header = digest::authorization(
+        challenge,
+        request.method().as_str(),
+        &uri,
+        username,
+        password,
+        body.as_deref(),
+    )?;
+    let value = HeaderValue::from_str(&header).map_err(|_|Error::InvalidHeaderValue(header.clone()))?;
+    request.headers_mut().insert(AUTHORIZATION, value);
+    execute(app, client, request, payload)
+}
+
+/// Credentials for Digest authentication, from --auth or else .netrc
+fn digest_credentials(app: &App, host: &str) -> HurlResult<(String, String)>{
+    if let Some(auth) = &app.auth{
+        let auth = credentials::resolve_auth(auth)?;
+        let (username, password) = parse_auth(&auth)?;
+        return Ok((username, password.unwrap_or_default()));
+    }
+    if !app.ignore_netrc{
+        if let Some(c) = netrc::lookup(app.netrc_file.as_ref(), host)?{
+            return Ok((c.login, c.password));
+        }
+    }
+    Err(Error::MissingCredentials(AuthType::Digest))
+}
+
+pub fn build_client(app: &App) -> HurlResult<Client>{
+    client_builder(app)?.build().map_err(From::from)
+}
+
+fn client_builder(app: &App) -> HurlResult<ClientBuilder>{
+    let mut builder = Client::builder();
+    if let Some(proxy) = &app.proxy{
+        trace!("Using proxy {}", proxy);
+        builder = builder.proxy(reqwest::Proxy::all(proxy.as_str())?);
+    }
+    if app.insecure{
+        trace!("Accepting invalid certificates");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    if let Some(path) = &app.cacert{
+        trace!("Adding root certificate {}", path.display());
+        let pem = std::fs::read(path)?;
+        builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+    }
+    Ok(builder)
+}
+
+/// Remove the headers unset with `Header:` from a request
+///
+/// This covers headers from the session, the configuration file and
+/// authentication. Accept-Encoding is only added by the client when it
+/// is not unset, while User-Agent and Accept cannot be unset at all.
+fn remove_unset_headers(request: &mut Request, parameters: &[Parameter]){
+    for param in parameters.iter(){
+        if let Parameter::UnsetHeader{key} = param{
+            trace!("Removing header: {}", key);
+            request.headers_mut().remove(key.as_str());
+        }
+    }
+}
+
+/// Whether the parameters, the session or the configuration file give
+/// the header `name`, so that no default is added for it
+fn sets_header(
+    name: &str,
+    parameters: &[Parameter],
+    session: &Option<Session>,
+    defaults: &config::Defaults,
+) -> bool{
+    parameters.iter().any(|p|p.is_header(name))
+        || session.as_ref().map(|s|s.has_header(name)).unwrap_or(false)
+        || defaults.headers.keys().any(|k|k.eq_ignore_ascii_case(name))
+}
+
+/// Headers and query parameters from the configuration file
+///
+/// These are skipped when the same header or query parameter is given on
+/// the command line, and headers also when the session stores them.
+fn handle_config_defaults(
+    mut builder: RequestBuilder,
+    defaults: config::Defaults,
+    session: &Option<Session>,
+    parameters: &[Parameter],
+) -> RequestBuilder{
+    for (key, value) in defaults.headers.iter(){
+        let in_session = session.as_ref().map(|s|s.has_header(key)).unwrap_or(false);
+        if in_session || parameters.iter().any(|p|p.is_header(key)){
+            continue;
+        }
+        trace!("Adding header from config: {}", key);
+        builder = builder.header(key.as_str(), value.as_str());
+    }
+    for (key, value) in defaults.query.iter(){
+        if parameters.iter().any(|p|p.is_query(key)){
+            continue;
+        }
+        trace!("Adding query parameter from config: {}", key);
+        builder = builder.query(&[(key, value)]);
+    }
+    builder
+}
+
 fn handle_auth(
     mut builder: RequestBuilder,
     auth: &Option<String>,
     token: &Option<String>,
+    auth_type: Option<AuthType>,
+    show_token_claims: bool,
 ) -> HurlResult<RequestBuilder>{
+    match auth_type{
+        Some(AuthType::Bearer) if token.is_none() => {
+            return Err(Error::MissingCredentials(AuthType::Bearer))
+        }
+        Some(AuthType::Bearer) => return handle_auth(builder, &None, token, None, show_token_claims),
+        Some(AuthType::Basic) => return handle_auth(builder, auth, &None, None, show_token_claims),
+        // Answered after the challenge in perform_digest
+        Some(AuthType::Digest) => return Ok(builder),
+        // Signed once the request is complete
+        Some(AuthType::AwsSigV4) => return Ok(builder),
+        None => {}
+    }
     if let Some(auth_string) = auth{
-        let (username, maybe_pasword) = parse_auth(&auth_string)?;
+        let auth_string = credentials::resolve_auth(auth_string)?;
+        let (username, maybe_password) = parse_auth(&auth_string)?;
         trace!(" Parsed basic autification. Username = {}", username);
         builder = builder.basic_auth(username, maybe_password);
     }
     if let Some(bearer) = token{
-        trace!(" Parsed bearer autification. Token{}", bearer);
-        builder = bulder.bearer_auth(bearer);
+        let bearer = credentials::resolve_token(bearer)?;
+        jwt::check_token(&bearer, show_token_claims);
+        builder = builder.bearer_auth(bearer);
     }
     Ok(builder)
 }
 
 
 
+/// Basic authentication from .netrc when no credentials are given
+///
+/// The session only records that the credentials came from .netrc so
+/// the password is not copied into it.
+fn handle_netrc(
+    mut builder: RequestBuilder,
+    app: &App,
+    host: &str,
+    session: &mut Option<Session>,
+) -> HurlResult<RequestBuilder>{
+    if app.ignore_netrc || app.auth.is_some() || app.token.is_some(){
+        return Ok(builder);
+    }
+    if let Some(credentials) = netrc::lookup(app.netrc_file.as_ref(), host)?{
+        trace!("Using credentials from netrc for {}", host);
+        builder = builder.basic_auth(&credentials.login, Some(&credentials.password));
+        if !app.read_only{
+            if let Some(s) = session{
+                s.update_netrc(&credentials.login);
+            }
+        }
+    }
+    Ok(builder)
+}
+
 fn handel_session(
     mut builder: RequestBuilder,
     session: &mut Option<Session>,
-    parameters: &Vec<Parameter>,
+    parameters: &[Parameter],
     update_session: bool,
     auth: &Option<String>,
     token: &Option<String>,
 ) -> RequestBuilder{
     if let Some(s) = session{
         trace!("Adding session data to request");
-        builder = s.add_to_request(builder);
+        builder = s.add_to_request(builder, parameters);
         if update_session{
             trace!("Update session with parameters from this request");
             s.update_with_parameters(parameters);
@@ -103,81 +525,186 @@ fn handel_session(
     }
     builder
 }
+/// Add the headers and query parameters to a request, and build the body
+/// from the data parameters
+///
+/// The Content-Type of a JSON or form body is only set when the
+/// parameters, the session or the configuration file do not give one.
 fn handle_parameters(
     mut builder: RequestBuilder,
     is_form: bool,
     is_multipart: bool,
-    parametrers: &Vec<Parameter>,
-) -> HurlResult<RequestBuilder>{
+    query_array: QueryArray,
+    parameters: &[Parameter],
+    has_content_type: bool,
+) -> HurlResult<(RequestBuilder, Option<Payload>)>{
     let mut data: HashMap<&String, Value> = HashMap::new();
+    let mut query: Vec<(&String, Vec<String>)> = Vec::new();
     let mut multipart = if is_multipart{
         Some(Form::new())
     } else {
         None
     };
 
-    for param in parameters.inter(){
+    for param in parameters.iter(){
         match param {
             Parameter::Header { key , value } => {
                 trace!("Access header: {}", key);
                 builder = builder.header(key, value);
             }
+            // Removed once the request is built
+            Parameter::UnsetHeader { .. } => {}
+            // Replaced by App::resolve_commands once the command line is parsed
+            Parameter::Command { .. } => {}
             Parameter::Data{key, value} => {
                 trace!("Addind data {}", key);
                 if multipart.is_none(){
                     data.insert(key, Value::String(value.to_owned()));
                 } else {
-                    multipart = multipart.mup(|m|m.text(key.to_owned(), value.to_owned()));
+                    multipart = multipart.map(|m|m.text(key.to_owned(), value.to_owned()));
 
                 }
             }
             Parameter::Query{ key, value} => {
                 trace!("Adding query parameter: {} ", key);
-                builder = builder.query(&[(key, value)]);
+                add_query(&mut query, key, value.to_owned());
+            }
+            Parameter::JsonQuery{ key, value} => {
+                trace!("Adding JSON query parameter: {} ", key);
+                let v: Value = serde_json::from_str(value)?;
+                add_query(&mut query, key, v.to_string());
             }
-            Parameter::RawJsonData { key : (), value:() } => {
+            Parameter::RawJsonData { key, value } => {
                 trace!(" Adding JSON data {}",key);
                 let v:Value = serde_json::from_str(value)?;
-                data.insert( key , v);
+                match multipart.take(){
+                    Some(m) => multipart = Some(m.part(key.to_owned(), json_part(&v))),
+                    None => {
+                        data.insert( key , v);
+                    }
+                }
             }
-            Parameter::RawJsonDataFile { key: (), filename: () } => {
+            Parameter::RawJsonDataFile { key, filename } => {
                 trace!(" Adding JSON data for key={} from file={}",key, filename);
-                let file = File::open(filename)?;
-                let reader = BufReader::new(file);
-                let v:Value = serde_json::from_reader(reader)?;
-                data.insert(key, v);
+                let v:Value = serde_json::from_slice(&source::read(filename)?)?;
+                match multipart.take(){
+                    Some(m) => multipart = Some(m.part(key.to_owned(), json_part(&v))),
+                    None => {
+                        data.insert(key, v);
+                    }
+                }
             }
             Parameter::DataFile {   key, filename} => {
                 trace!(" Adding data from file = {} for key = {}", filename, key);
 
 
-                let value = std::fs::read_to_string(filename)?;
-                data.insert(key, Value::String(value));
+                let value = read_text_file(filename)?;
+                match multipart.take(){
+                    Some(m) => multipart = Some(m.text(key.to_owned(), value)),
+                    None => {
+                        data.insert(key, Value::String(value));
+                    }
+                }
+            }
+            Parameter::Base64DataFile{key, filename} => {
+                trace!(" Adding base64 data from file = {} for key = {}", filename, key);
+                let value = base64::encode(&source::read(filename)?);
+                match multipart.take(){
+                    Some(m) => multipart = Some(m.text(key.to_owned(), value)),
+                    None => {
+                        data.insert(key, Value::String(value));
+                    }
+                }
             }
-            Parameter::FromFile{key, filename} => {
+            Parameter::FormFile{key, filename, options} => {
                 trace!("Adding file = {} , with key = {}", filename, key);
                 multipart = Some(
                     multipart
                     .unwrap()
-                    .file(key.to_owned(),filename.to_owned())?,
+                    .part(key.to_owned(), file_part(filename, options)?),
                 );
             }
         }
-        if let Some(m) = multipart{ 
-            bulder = builder.multipart(m);
-        } else {
-            if !data.is_empty(){
-                if is_form{
-                    builder = builder.form(&data);
-                } else {
-                    builder = builder.json(&data);
-                }
+    }
+    if !query.is_empty(){
+        builder = builder.query(&encode_query(query, query_array));
+    }
+    let payload = if let Some(m) = multipart{
+        builder = builder.header(CONTENT_TYPE, m.content_type());
+        Some(Payload::Form(m))
+    } else if data.is_empty(){
+        None
+    } else if is_form{
+        if !has_content_type{
+            builder = builder.header(CONTENT_TYPE, "application/x-www-form-urlencoded");
+        }
+        let form = serde_urlencoded::to_string(&data).map_err(|_|Error::ClientSerialization)?;
+        Some(Payload::Bytes(form.into_bytes()))
+    } else {
+        if !has_content_type{
+            builder = builder.header(CONTENT_TYPE, "application/json");
+        }
+        Some(Payload::Bytes(serde_json::to_vec(&data)?))
+    };
+    Ok((builder, payload))
+}
+
+/// Collect the values of query parameters by name, keeping their order
+fn add_query<'a>(query: &mut Vec<(&'a String, Vec<String>)>, key: &'a String, value: String){
+    match query.iter_mut().find(|(k, _)|*k == key){
+        Some((_, values)) => values.push(value),
+        None => query.push((key, vec![value])),
+    }
+}
+
+/// The query pairs for parameters given once or more, in the given style
+fn encode_query(query: Vec<(&String, Vec<String>)>, style: QueryArray) -> Vec<(String, String)>{
+    let mut pairs = Vec::new();
+    for (key, values) in query{
+        if values.len() == 1{
+            pairs.push((key.clone(), values.into_iter().next().unwrap()));
+            continue;
+        }
+        match style{
+            QueryArray::Repeat => pairs.extend(values.into_iter().map(|v|(key.clone(), v))),
+            QueryArray::Brackets => {
+                pairs.extend(values.into_iter().map(|v|(format!("{}[]", key), v)))
             }
+            QueryArray::Comma => pairs.push((key.clone(), values.join(","))),
         }
-        Ok(builder)
     }
+    pairs
+}
 
+/// The contents of a `key=@filename` file, which must be UTF-8
+fn read_text_file(filename: &str) -> HurlResult<String>{
+    let bytes = source::read(filename)?;
+    String::from_utf8(bytes).map_err(|_|Error::FileNotUtf8(filename.to_owned()))
+}
 
+/// The part for `key@filename`, where a filename of - reads stdin and is
+/// sent as a file named stdin
+fn file_part(filename: &str, options: &PartOptions) -> HurlResult<Part>{
+    let mut part = if filename == "-"{
+        Part::bytes(source::read(filename)?)
+            .mime_str("application/octet-stream")
+            .file_name("stdin".to_owned())
+    } else {
+        Part::file(Path::new(filename))?
+    };
+    if let Some(content_type) = &options.content_type{
+        part = part.mime_str(content_type);
+    }
+    if let Some(file_name) = &options.file_name{
+        part = part.file_name(file_name.clone());
+    }
+    Ok(part)
+}
+
+/// An application/json part for `key:=value` and `key:=@file` in a
+/// multipart form
+fn json_part(value: &Value) -> Part{
+    Part::text(value.to_string()).mime_str("application/json")
 }
  
 
@@ -201,13 +728,13 @@ fn parse_auth(s:&str) -> HurlResult<(String, Option<String>)>{
         let (username, password_in_colon) = s.split_at(idx);
         let password = password_in_colon.trim_start_matches(':');
         if password.is_empty(){
-            return Ok((username.to_owned(), None));
+            Ok((username.to_owned(), None))
         } else {
-            return Ok((username.to_owned(), Some(password.to_owned())));
+            Ok((username.to_owned(), Some(password.to_owned())))
         }
     } else {
-        let password = rpasword::read_password_from_tty(Some("Password:"))?;
-        return Ok((s.to_owned(), Some(password)));
+        let password = rpassword::read_password_from_tty(Some("Password:"))?;
+        Ok((s.to_owned(), Some(password)))
     }
 }
 
@@ -267,11 +794,16 @@ fn parse_auth(s:&str) -> HurlResult<(String, Option<String>)>{
 
 
 
-fn parse(app: &App, s: &str) -> Result<Url, reqwest:UrlError>{
+pub fn parse(app: &App, s: &str) -> Result<Url, reqwest::UrlError>{
+    if s.starts_with('/'){
+        if let Some(base) = &app.base_url{
+            return Url::parse(&format!("{}{}", base.trim_end_matches('/'), s));
+        }
+    }
     if s.starts_with(":/"){
         return Url::parse(&format!("http://localhost{}", &s[1..]));
     } else if s.starts_with(":"){
-        return Url::pqrse(&format!("http://localhost{}",s));
+        return Url::parse(&format!("http://localhost{}",s));
     }
     match Url::parse(s){
         Ok(url) => Ok(url),
@@ -279,15 +811,47 @@ fn parse(app: &App, s: &str) -> Result<Url, reqwest:UrlError>{
             if app.secure{
                 Url::parse(&format!("https://{}",s))
             } else {
-                Url::parse(&format!("htpp://{}",s))
+                Url::parse(&format!("http://{}",s))
             }
         }
     }
     }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn finds_headers_from_every_source() {
+        let header = |key: &str| Parameter::Header{key: key.to_owned(), value: "x".to_owned()};
+        let none = config::Defaults::default();
+        assert!(!sets_header("content-type", &[], &None, &none));
+        assert!(sets_header("content-type", &[header("Content-Type")], &None, &none));
 
+        let mut session = Session::default();
+        session.update_with_parameters(&[header("Depth")]);
+        assert!(sets_header("depth", &[], &Some(session), &none));
 
+        let mut defaults = config::Defaults::default();
+        defaults.headers.insert("CONTENT-TYPE".to_owned(), "x".to_owned());
+        assert!(sets_header("content-type", &[], &None, &defaults));
+    }
 
-
-}
\ No newline at end of file
+    #[test]
+    fn encodes_repeated_query_parameters() {
+        let (tag, page) = ("tag".to_owned(), "page".to_owned());
+        let encode = |style: QueryArray| {
+            let mut query = Vec::new();
+            add_query(&mut query, &tag, "a".to_owned());
+            add_query(&mut query, &page, "1".to_owned());
+            add_query(&mut query, &tag, "b".to_owned());
+            encode_query(query, style)
+                .into_iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(encode(QueryArray::Repeat), ["tag=a", "tag=b", "page=1"]);
+        assert_eq!(encode(QueryArray::Brackets), ["tag[]=a", "tag[]=b", "page=1"]);
+        assert_eq!(encode(QueryArray::Comma), ["tag=a,b", "page=1"]);
+    }
+}
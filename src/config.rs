@@ -1,17 +1,186 @@
+use log::trace;
 use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
 
-use crate::app::App;
+use crate::app::{parse_param, App, ConfigCommand, Parameter};
 use crate::directories::DIRECTORIES;
+use crate::errors::{Error, HurlResult};
+use crate::oauth;
+use crate::signing;
 
-#[derive(Debug, Deserialize)]
+/// The keys of each table in the configuration file, for suggesting a
+/// key when an unknown one is used
+const CONFIG_KEYS: &[&str] = &[
+    "verbose", "form", "auth", "token", "secure", "headers", "query", "hosts", "profiles",
+    "requests", "oauth", "signing",
+];
+const PROFILE_KEYS: &[&str] = &[
+    "base_url", "headers", "query", "auth", "token", "insecure", "cacert", "proxy", "oauth", "sign",
+];
+const DEFAULTS_KEYS: &[&str] = &["headers", "query"];
+const REQUEST_KEYS: &[&str] = &["method", "url", "headers", "query", "data", "parameters"];
+const OAUTH_KEYS: &[&str] = &[
+    "token_url", "client_id", "client_secret", "scopes", "grant", "authorization_url",
+    "redirect_port", "username", "password", "url",
+];
+const SIGNING_KEYS: &[&str] = &[
+    "algorithm", "secret", "template", "encoding", "header", "prefix", "timestamp_header",
+    "timestamp_format", "nonce_header",
+];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config{
     pub verbose: Option<u8>,
     pub form: Option<bool>,
     pub auth: Option<String>,
     pub token: Option<String>,
     pub secure: Option<bool>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub query: BTreeMap<String, String>,
+    #[serde(default)]
+    pub hosts: BTreeMap<String, Defaults>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    #[serde(default)]
+    pub requests: HashMap<String, SavedRequest>,
+    #[serde(default)]
+    pub oauth: HashMap<String, oauth::Provider>,
+    #[serde(default)]
+    pub signing: HashMap<String, signing::Scheme>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile{
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub query: BTreeMap<String, String>,
+    pub auth: Option<String>,
+    pub token: Option<String>,
+    pub insecure: Option<bool>,
+    pub cacert: Option<PathBuf>,
+    pub proxy: Option<String>,
+    /// The name of the [oauth.NAME] provider to authenticate with
+    pub oauth: Option<String>,
+    /// The name of the [signing.NAME] scheme to sign requests with
+    pub sign: Option<String>,
+}
+
+/// A request saved under a name, run with `hurl @NAME`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SavedRequest{
+    pub method: Option<String>,
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub query: BTreeMap<String, String>,
+    #[serde(default)]
+    pub data: BTreeMap<String, String>,
+    /// Any other parameters in command line syntax, e.g. "ids:=[1,2]"
+    #[serde(default)]
+    pub parameters: Vec<String>,
+}
+
+impl SavedRequest{
+    pub fn method(&self) -> HurlResult<Option<reqwest::Method>>{
+        match &self.method{
+            Some(m) => reqwest::Method::from_bytes(m.to_ascii_uppercase().as_bytes())
+                .map(Some)
+                .map_err(|_|Error::InvalidMethod(m.clone())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn parameters(&self) -> HurlResult<Vec<Parameter>>{
+        let mut parameters = Vec::new();
+        for (key, value) in self.headers.iter(){
+            parameters.push(Parameter::Header{key: key.clone(), value: value.clone()});
+        }
+        for (key, value) in self.query.iter(){
+            parameters.push(Parameter::Query{key: key.clone(), value: value.clone()});
+        }
+        for (key, value) in self.data.iter(){
+            parameters.push(Parameter::Data{key: key.clone(), value: value.clone()});
+        }
+        for p in self.parameters.iter(){
+            parameters.push(parse_param(p)?);
+        }
+        Ok(parameters)
+    }
+}
+
+/// Headers and query parameters added to every request
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults{
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub query: BTreeMap<String, String>,
+}
+
+impl Defaults{
+    /// Add the entries from `other` which are not set already
+    pub fn fill_from(&mut self, other: Defaults){
+        for (key, value) in other.headers{
+            if !self.headers.keys().any(|k|k.eq_ignore_ascii_case(&key)){
+                self.headers.insert(key, value);
+            }
+        }
+        for (key, value) in other.query{
+            self.query.entry(key).or_insert(value);
+        }
+    }
+}
+
+/// Match a host against a pattern such as `*.internal.example.com`
+///
+/// A leading `*.` matches any number of subdomains and `*` on its own
+/// matches every host.
+pub fn host_matches(pattern: &str, host: &str) -> bool{
+    if pattern == "*"{
+        return true;
+    }
+    if pattern.starts_with("*."){
+        let suffix = &pattern[1..];
+        return host.len() > suffix.len()
+            && host
+                .get(host.len() - suffix.len()..)
+                .is_some_and(|end|end.eq_ignore_ascii_case(suffix));
+    }
+    pattern.eq_ignore_ascii_case(host)
+}
+
+/// The defaults which apply to requests to `host`
+///
+/// Host specific defaults take precedence over the global ones, and
+/// longer, more specific, patterns over shorter ones.
+pub fn defaults_for_host(app: &App, host: &str) -> Defaults{
+    let mut matching: Vec<&(String, Defaults)> = app
+        .host_defaults
+        .iter()
+        .filter(|(pattern, _)|host_matches(pattern, host))
+        .collect();
+    matching.sort_by_key(|(pattern, _)|std::cmp::Reverse(pattern.len()));
+
+    let mut defaults = Defaults::default();
+    for (_, d) in matching{
+        defaults.fill_from(d.clone());
+    }
+    defaults.fill_from(app.config_defaults.clone());
+    defaults
 }
 
 pub fn config_file(app: &App) -> PathBuf{
@@ -22,9 +191,421 @@ pub fn config_file(app: &App) -> PathBuf{
         .unwrap_or_else(|| DIRECTORIES.config().join("config"))
 }
 
-pub fn read_config_file(path: PathBuf) -> Option<Config>{
-    fs::read_to_str(path).ok().map(|content|{
-        let config::Config = toml::from_str(&content).unwrap();
-        config
+/// Read the configuration file, if there is one
+///
+/// A missing file is not an error, but a file which cannot be parsed is.
+pub fn read_config_file(path: PathBuf) -> HurlResult<Option<Config>>{
+    let content = match fs::read_to_string(&path){
+        Ok(content) => content,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    parse_config(&path, &content).map(Some)
+}
+
+fn parse_config(path: &Path, content: &str) -> HurlResult<Config>{
+    toml::from_str(content).map_err(|e|{
+        let mut line = e.line_col().map(|(line, _)|line + 1);
+        let value = toml::from_str::<toml::Value>(content).ok();
+        let unknown = value.as_ref().and_then(unknown_key);
+        // toml gives the line of the table for an unknown field
+        if let Some((table, key, _)) = &unknown{
+            line = key_line(content, table, key).or(line);
+        }
+        let message = match unknown{
+            Some((mut path, key, suggestion)) => {
+                path.push(key);
+                match suggestion{
+                    Some(suggestion) => {
+                        format!("unknown key `{}`, did you mean `{}`?", path.join("."), suggestion)
+                    }
+                    None => format!("unknown key `{}`", path.join(".")),
+                }
+            }
+            None => {
+                let mut message = e.to_string();
+                if let Some(idx) = message.rfind(" at line "){
+                    message.truncate(idx);
+                }
+                message
+            }
+        };
+        Error::ConfigParse(path.to_owned(), line, message)
     })
-}
\ No newline at end of file
+}
+
+/// The first key in the configuration which is not known, with the path
+/// of its table, and the closest known key
+fn unknown_key(config: &toml::Value) -> Option<(Vec<&str>, &str, Option<&'static str>)>{
+    let tables: [(&str, &[&str]); 5] = [
+        ("profiles", PROFILE_KEYS),
+        ("hosts", DEFAULTS_KEYS),
+        ("requests", REQUEST_KEYS),
+        ("oauth", OAUTH_KEYS),
+        ("signing", SIGNING_KEYS),
+    ];
+    let config = config.as_table()?;
+    if let Some((key, suggestion)) = unknown_in(config, CONFIG_KEYS){
+        return Some((Vec::new(), key, suggestion));
+    }
+    for (name, known) in tables.iter(){
+        let named = match config.get(*name).and_then(|t|t.as_table()){
+            Some(named) => named,
+            None => continue,
+        };
+        for (key, table) in named.iter(){
+            if let Some(table) = table.as_table(){
+                if let Some((unknown, suggestion)) = unknown_in(table, known){
+                    return Some((vec![name, key], unknown, suggestion));
+                }
+            }
+        }
+    }
+    None
+}
+
+fn unknown_in<'a>(
+    table: &'a toml::value::Table,
+    known: &[&'static str],
+) -> Option<(&'a str, Option<&'static str>)>{
+    let key = table.keys().find(|key|!known.contains(&key.as_str()))?;
+    let suggestion = known
+        .iter()
+        .map(|candidate|(edit_distance(key, candidate), *candidate))
+        .filter(|(distance, _)|*distance <= 3)
+        .min_by_key(|(distance, _)|*distance)
+        .map(|(_, candidate)|candidate);
+    Some((key, suggestion))
+}
+
+/// The line, counting from 1, on which `key` is set in the table `table`
+///
+/// Keys set in a `[table]` section or as a dotted key are found, keys in
+/// inline tables are not.
+fn key_line(content: &str, table: &[&str], key: &str) -> Option<usize>{
+    let wanted: Vec<&str> = table.iter().cloned().chain(Some(key)).collect();
+    let mut current = Vec::new();
+    for (i, line) in content.lines().enumerate(){
+        let line = line.trim();
+        if line.starts_with('['){
+            let header = line.trim_start_matches('[');
+            current = split_key(&header[..header.rfind(']').unwrap_or(header.len())]);
+            continue;
+        }
+        let name = match line.find('='){
+            Some(idx) => &line[..idx],
+            None => continue,
+        };
+        let mut path = current.clone();
+        path.extend(split_key(name));
+        if path == wanted{
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+/// The parts of a dotted TOML key, without their quotes
+fn split_key(s: &str) -> Vec<String>{
+    let mut parts = vec![String::new()];
+    let mut quote = None;
+    for c in s.trim().chars(){
+        match quote{
+            Some(q) if c == q => quote = None,
+            Some(_) => parts.last_mut().unwrap().push(c),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '.' => parts.push(String::new()),
+            None if c.is_whitespace() => {}
+            None => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+}
+
+fn edit_distance(a: &str, b: &str) -> usize{
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate(){
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate(){
+            let cost = if ca == *cb { 0 } else { 1 };
+            let value = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+            current.push(value);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+pub fn run_command(app: &App, cmd: &ConfigCommand) -> HurlResult<()>{
+    match cmd{
+        ConfigCommand::Path => {
+            println!("{}", config_file(app).display());
+            Ok(())
+        }
+        ConfigCommand::Validate => {
+            let path = config_file(app);
+            match read_config_file(path.clone())?{
+                Some(_) => println!("{} is valid", path.display()),
+                None => println!("{} does not exist", path.display()),
+            }
+            Ok(())
+        }
+        ConfigCommand::Edit => edit(app),
+        ConfigCommand::Show => show(app),
+    }
+}
+
+fn edit(app: &App) -> HurlResult<()>{
+    let path = app
+        .config
+        .clone()
+        .unwrap_or_else(|| DIRECTORIES.config().join("config"));
+    if let Some(dir) = path.parent(){
+        fs::create_dir_all(dir)?;
+    }
+    let editor = env::var("VISUAL")
+        .or_else(|_|env::var("EDITOR"))
+        .unwrap_or_else(|_|"vi".to_owned());
+    trace!("Editing {} with {}", path.display(), editor);
+    let status = process::Command::new(&editor).arg(&path).status()?;
+    if !status.success(){
+        return Err(Error::EditorFailed(editor));
+    }
+    read_config_file(path.clone())?;
+    println!("{} is valid", path.display());
+    Ok(())
+}
+
+/// Print the effective configuration and where each value came from
+///
+/// `app` must be the command line arguments before the configuration
+/// file has been merged into them.
+fn show(app: &App) -> HurlResult<()>{
+    let path = config_file(app);
+    let config = read_config_file(path.clone())?;
+    let (mut config, file) = match config{
+        Some(config) => (config, format!("config file {}", path.display())),
+        None => (Config::default(), "default".to_owned()),
+    };
+    let profile = match &app.profile{
+        Some(name) => {
+            let profile = config
+                .profiles
+                .remove(name)
+                .ok_or_else(|| Error::UnknownProfile(name.clone()))?;
+            Some((format!("profile {}", name), profile))
+        }
+        None => None,
+    };
+    let from_profile = |f: &dyn Fn(&Profile) -> Option<String>|{
+        profile.as_ref().and_then(|(origin, p)|f(p).map(|v|(v, origin.clone())))
+    };
+
+    // Each key is shown once, with the value which takes effect, and
+    // host defaults under the pattern of the hosts they apply to
+    let mut lines: Vec<(Option<String>, String, String, String)> = Vec::new();
+    let mut add = |pattern: Option<&str>, key: &str, candidates: Vec<Option<(String, String)>>|{
+        let shown = lines
+            .iter()
+            .any(|(p, k, _, _)|p.as_deref() == pattern && same_key(k, key));
+        if shown{
+            return;
+        }
+        if let Some((value, origin)) = candidates.into_iter().flatten().next(){
+            lines.push((pattern.map(str::to_owned), key.to_owned(), value, origin));
+        }
+    };
+    let cli = |value: Option<String>|value.map(|v|(v, "command line".to_owned()));
+    let in_file = |value: Option<String>|value.map(|v|(v, file.clone()));
+
+    add(None, "verbose", vec![
+        cli(Some(app.verbose).filter(|v|*v > 0).map(|v|v.to_string())),
+        in_file(config.verbose.map(|v|v.to_string())),
+    ]);
+    add(None, "form", vec![
+        cli(Some(app.form).filter(|f|*f).map(|f|f.to_string())),
+        in_file(config.form.map(|f|f.to_string())),
+    ]);
+    add(None, "secure", vec![
+        cli(Some(app.secure).filter(|s|*s).map(|s|s.to_string())),
+        in_file(config.secure.map(|s|s.to_string())),
+    ]);
+    add(None, "auth", vec![
+        cli(app.auth.as_ref().map(|a|mask_auth(a))),
+        from_profile(&|p|p.auth.as_ref().map(|a|mask_auth(a))),
+        in_file(config.auth.as_ref().map(|a|mask_auth(a))),
+    ]);
+    add(None, "token", vec![
+        cli(app.token.as_ref().map(|_|MASK.to_owned())),
+        from_profile(&|p|p.token.as_ref().map(|_|MASK.to_owned())),
+        in_file(config.token.as_ref().map(|_|MASK.to_owned())),
+    ]);
+    add(None, "base_url", vec![
+        cli(app.base_url.clone()),
+        from_profile(&|p|p.base_url.clone()),
+    ]);
+    add(None, "proxy", vec![
+        cli(app.proxy.clone()),
+        from_profile(&|p|p.proxy.clone()),
+    ]);
+    add(None, "insecure", vec![
+        cli(Some(app.insecure).filter(|i|*i).map(|i|i.to_string())),
+        from_profile(&|p|p.insecure.map(|i|i.to_string())),
+    ]);
+    add(None, "cacert", vec![
+        cli(app.cacert.as_ref().map(|c|c.display().to_string())),
+        from_profile(&|p|p.cacert.as_ref().map(|c|c.display().to_string())),
+    ]);
+    add(None, "oauth", vec![
+        cli(app.oauth.clone()),
+        from_profile(&|p|p.oauth.clone()),
+    ]);
+    add(None, "sign", vec![
+        cli(app.sign.clone()),
+        from_profile(&|p|p.sign.clone()),
+    ]);
+    if let Some((origin, p)) = &profile{
+        for (key, value) in &p.headers{
+            add(None, &format!("headers.{}", key), vec![Some((value.clone(), origin.clone()))]);
+        }
+        for (key, value) in &p.query{
+            add(None, &format!("query.{}", key), vec![Some((value.clone(), origin.clone()))]);
+        }
+    }
+    for (key, value) in &config.headers{
+        add(None, &format!("headers.{}", key), vec![in_file(Some(value.clone()))]);
+    }
+    for (key, value) in &config.query{
+        add(None, &format!("query.{}", key), vec![in_file(Some(value.clone()))]);
+    }
+    for (pattern, defaults) in &config.hosts{
+        for (key, value) in &defaults.headers{
+            add(Some(pattern), &format!("headers.{}", key), vec![in_file(Some(value.clone()))]);
+        }
+        for (key, value) in &defaults.query{
+            add(Some(pattern), &format!("query.{}", key), vec![in_file(Some(value.clone()))]);
+        }
+    }
+
+    let width = lines.iter().map(|(_, k, _, _)|k.len()).max().unwrap_or(0);
+    let mut section = None;
+    for (pattern, key, value, origin) in lines.iter(){
+        if pattern.is_some() && *pattern != section{
+            println!();
+            println!("# for hosts matching \"{}\"", pattern.as_deref().unwrap_or(""));
+            section = pattern.clone();
+        }
+        println!("{:width$} = {}    # {}", key, value, origin, width = width);
+    }
+    Ok(())
+}
+
+/// Whether two keys shown by `show` are the same, header names being
+/// case insensitive
+fn same_key(a: &str, b: &str) -> bool{
+    if a.starts_with("headers.") && b.starts_with("headers."){
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+const MASK: &str = "********";
+
+fn mask_auth(auth: &str) -> String{
+    match auth.find(':'){
+        Some(idx) => format!("{}:{}", &auth[..idx], MASK),
+        None => auth.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    fn parse_error(content: &str) -> String {
+        match parse_config(Path::new("config"), content) {
+            Err(Error::ConfigParse(_, _, message)) => message,
+            other => panic!("expected an error: {:?}", other),
+        }
+    }
+
+    fn error_line(content: &str) -> Option<usize> {
+        match parse_config(Path::new("config"), content) {
+            Err(Error::ConfigParse(_, line, _)) => line,
+            other => panic!("expected an error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn points_at_unknown_keys() {
+        assert_eq!(error_line("verbose = 1\n\nverbos = 1\n"), Some(3));
+        let profiles = "[profiles.dev]\nbase_url = \"x\"\n\n[profiles.prod]\nbase_url = \"y\"\nbse_url = \"z\"\n";
+        assert_eq!(error_line(profiles), Some(6));
+        let quoted = "[hosts.\"*.example.com\"]\nheaders = {}\n'qury' = {}\n";
+        assert_eq!(parse_error(quoted), "unknown key `hosts.*.example.com.qury`, did you mean `query`?");
+        assert_eq!(error_line(quoted), Some(3));
+        assert_eq!(error_line("form = true\nprofiles.dev.bse_url = \"x\"\n"), Some(2));
+    }
+
+    #[test]
+    fn suggests_known_keys() {
+        assert_eq!(parse_error("verbos = 1"), "unknown key `verbos`, did you mean `verbose`?");
+        assert_eq!(
+            parse_error("[profiles.dev]\nbse_url = \"x\""),
+            "unknown key `profiles.dev.bse_url`, did you mean `base_url`?"
+        );
+        assert_eq!(
+            parse_error("[oauth.p]\ntoken_url = \"x\"\nclient_id = \"c\"\nredirect = 1"),
+            "unknown key `oauth.p.redirect`"
+        );
+    }
+
+    #[test]
+    fn accepts_known_keys() {
+        let config = parse_config(
+            Path::new("config"),
+            "verbose = 1\n[headers]\nX-A = \"1\"\n[hosts.\"*.example.com\"]\nquery = { a = \"1\" }",
+        )
+        .unwrap();
+        assert_eq!(config.verbose, Some(1));
+        assert_eq!(config.hosts["*.example.com"].query["a"], "1");
+    }
+
+    #[test]
+    fn matches_hosts_case_insensitively() {
+        assert!(host_matches("*", "example.com"));
+        assert!(host_matches("*.example.com", "api.example.com"));
+        assert!(host_matches("*.Example.COM", "a.b.example.com"));
+        assert!(host_matches("*.example.com", "API.EXAMPLE.COM"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", "badexample.com.evil"));
+        assert!(host_matches("API.example.com", "api.example.com"));
+    }
+
+    #[test]
+    fn keeps_headers_in_order() {
+        let config = parse_config(
+            Path::new("config"),
+            "[headers]\nX-C = \"3\"\nX-A = \"1\"\nX-B = \"2\"\n\
+             [hosts.\"*.example.com\".headers]\nX-A = \"host\"\n\
+             [hosts.\"api.example.com\".headers]\nX-B = \"api\"",
+        )
+        .unwrap();
+        let mut app = App::from_iter_safe(&["hurl", "http://x"]).unwrap();
+        app.config_defaults = Defaults{headers: config.headers, query: config.query};
+        app.host_defaults = config.hosts.into_iter().collect();
+
+        let defaults = defaults_for_host(&app, "api.example.com");
+        let headers: Vec<(&str, &str)> = defaults
+            .headers
+            .iter()
+            .map(|(k, v)|(k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(headers, [("X-A", "host"), ("X-B", "api"), ("X-C", "3")]);
+    }
+}
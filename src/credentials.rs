@@ -0,0 +1,217 @@
+use crate::app::{App, AuthCommand};
+use crate::errors::{Error, HurlResult};
+use crate::oauth;
+use log::trace;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Once;
+
+const PREFIX: &str = "keyring:";
+
+/// Warns once that the HURL_KEYRING_FILE fallback is used
+static FALLBACK_WARNING: Once = Once::new();
+
+/// A reference to a secret in the OS keyring, written `keyring:service/user`
+#[derive(Debug)]
+pub struct KeyringRef{
+    pub service: String,
+    pub user: String,
+}
+
+impl KeyringRef{
+    pub fn parse(s: &str) -> HurlResult<KeyringRef>{
+        let s = s.trim_start_matches(PREFIX);
+        match s.rfind('/'){
+            Some(idx) if idx > 0 && idx < s.len() - 1 => Ok(KeyringRef{
+                service: s[..idx].to_owned(),
+                user: s[idx + 1..].to_owned(),
+            }),
+            _ => Err(Error::InvalidKeyringRef(s.to_owned())),
+        }
+    }
+
+    fn id(&self) -> String{
+        format!("{}/{}", self.service, self.user)
+    }
+}
+
+pub fn run_command(app: &App, cmd: &AuthCommand) -> HurlResult<()>{
+    match cmd{
+        AuthCommand::Login { provider, url } => oauth::login(app, provider, url.as_deref()),
+        AuthCommand::Store { entry } => {
+            let r = KeyringRef::parse(entry)?;
+            let secret = rpassword::read_password_from_tty(Some("Secret: "))?;
+            store(&r, &secret)?;
+            println!("Stored {}{}", PREFIX, r.id());
+            Ok(())
+        }
+        AuthCommand::Forget { entry } => {
+            let r = KeyringRef::parse(entry)?;
+            forget(&r)?;
+            println!("Removed {}{}", PREFIX, r.id());
+            Ok(())
+        }
+    }
+}
+
+pub fn is_keyring_ref(s: &str) -> bool{
+    s.starts_with(PREFIX)
+}
+
+/// Resolve `--auth` given as `keyring:service/user` to `user:password`
+pub fn resolve_auth(auth: &str) -> HurlResult<String>{
+    if !is_keyring_ref(auth){
+        return Ok(auth.to_owned());
+    }
+    let r = KeyringRef::parse(auth)?;
+    let password = get(&r)?;
+    Ok(format!("{}:{}", r.user, password))
+}
+
+/// Resolve `--token` given as `keyring:service/user` to the stored token
+pub fn resolve_token(token: &str) -> HurlResult<String>{
+    if !is_keyring_ref(token){
+        return Ok(token.to_owned());
+    }
+    get(&KeyringRef::parse(token)?)
+}
+
+/// The file used instead of the OS keyring, if HURL_KEYRING_FILE is set
+///
+/// This is meant for systems without a Secret Service, such as CI
+/// containers, and stores secrets unencrypted.
+fn fallback_file() -> Option<PathBuf>{
+    let path = PathBuf::from(env::var_os("HURL_KEYRING_FILE")?);
+    FALLBACK_WARNING.call_once(|| {
+        eprintln!(
+            "Warning: HURL_KEYRING_FILE is set, secrets are kept unencrypted in {}",
+            path.display()
+        )
+    });
+    Some(path)
+}
+
+pub fn get(r: &KeyringRef) -> HurlResult<String>{
+    trace!("Reading {} from keyring", r.id());
+    if let Some(path) = fallback_file(){
+        return read_file(&path)?
+            .remove(&r.id())
+            .ok_or_else(|| Error::KeyringEntryNotFound(r.id()));
+    }
+    keyring::Entry::new(&r.service, &r.user)
+        .get_password()
+        .map_err(|e| match e{
+            keyring::Error::NoEntry => Error::KeyringEntryNotFound(r.id()),
+            e => Error::Keyring(e.to_string()),
+        })
+}
+
+pub fn store(r: &KeyringRef, secret: &str) -> HurlResult<()>{
+    trace!("Storing {} in keyring", r.id());
+    if let Some(path) = fallback_file(){
+        let mut secrets = read_file(&path)?;
+        secrets.insert(r.id(), secret.to_owned());
+        return write_file(&path, &secrets);
+    }
+    keyring::Entry::new(&r.service, &r.user)
+        .set_password(secret)
+        .map_err(|e| Error::Keyring(e.to_string()))
+}
+
+pub fn forget(r: &KeyringRef) -> HurlResult<()>{
+    trace!("Removing {} from keyring", r.id());
+    if let Some(path) = fallback_file(){
+        let mut secrets = read_file(&path)?;
+        if secrets.remove(&r.id()).is_none(){
+            return Err(Error::KeyringEntryNotFound(r.id()));
+        }
+        return write_file(&path, &secrets);
+    }
+    keyring::Entry::new(&r.service, &r.user)
+        .delete_password()
+        .map_err(|e| match e{
+            keyring::Error::NoEntry => Error::KeyringEntryNotFound(r.id()),
+            e => Error::Keyring(e.to_string()),
+        })
+}
+
+fn read_file(path: &PathBuf) -> HurlResult<BTreeMap<String, String>>{
+    if !path.is_file(){
+        return Ok(BTreeMap::new());
+    }
+    let content = fs::read(path)?;
+    serde_json::from_slice(&content).map_err(From::from)
+}
+
+/// Write the secrets to a new file only the user can read, which then
+/// replaces the one at `path`, so they are never readable by others
+fn write_file(path: &PathBuf, secrets: &BTreeMap<String, String>) -> HurlResult<()>{
+    let mut tmp = path.clone().into_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    // Left over from an interrupted write, possibly with other permissions
+    if tmp.is_file(){
+        fs::remove_file(&tmp)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp)?;
+    file.write_all(&serde_json::to_vec_pretty(secrets)?)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_references() {
+        let r = KeyringRef::parse("keyring:api/ci/deploy").unwrap();
+        assert_eq!((r.service.as_str(), r.user.as_str()), ("api/ci", "deploy"));
+        for bad in &["keyring:api", "keyring:/user", "keyring:api/"] {
+            assert!(matches!(KeyringRef::parse(bad), Err(Error::InvalidKeyringRef(_))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn stores_gets_and_forgets_in_the_fallback_file() {
+        let dir = env::temp_dir().join(format!("hurl-keyring-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("secrets.json");
+        env::set_var("HURL_KEYRING_FILE", &path);
+        let api = KeyringRef::parse("keyring:api/ann").unwrap();
+        let db = KeyringRef::parse("keyring:db/bob").unwrap();
+
+        assert!(matches!(get(&api), Err(Error::KeyringEntryNotFound(_))));
+        store(&api, "s3cret").unwrap();
+        store(&db, "other").unwrap();
+        store(&api, "changed").unwrap();
+        assert_eq!(get(&api).unwrap(), "changed");
+        assert_eq!(resolve_auth("keyring:api/ann").unwrap(), "ann:changed");
+        assert_eq!(resolve_token("keyring:db/bob").unwrap(), "other");
+        assert_eq!(resolve_token("plain").unwrap(), "plain");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        forget(&api).unwrap();
+        assert!(matches!(get(&api), Err(Error::KeyringEntryNotFound(_))));
+        assert!(matches!(forget(&api), Err(Error::KeyringEntryNotFound(_))));
+        assert_eq!(get(&db).unwrap(), "other");
+
+        env::remove_var("HURL_KEYRING_FILE");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+use crate::errors::{Error, HurlResult};
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::Request;
+use sha2::{Sha256, Sha512};
+
+/// Lowercase hex, as signatures and hashes are written
+pub fn hex(bytes: &[u8]) -> String{
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8>{
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+pub fn hmac_sha512(key: &[u8], data: &[u8]) -> Vec<u8>{
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Set a header on a built request, replacing any value it had
+pub fn insert(request: &mut Request, name: &str, value: &str) -> HurlResult<()>{
+    let name = HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| Error::InvalidHeaderName(name.to_owned()))?;
+    let value = HeaderValue::from_str(value).map_err(|_| Error::InvalidHeaderValue(value.to_owned()))?;
+    request.headers_mut().insert(name, value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 4231 test case 2
+    const KEY: &[u8] = b"Jefe";
+    const DATA: &[u8] = b"what do ya want for nothing?";
+
+    #[test]
+    fn hmac_sha256_matches_rfc_4231() {
+        assert_eq!(
+            hex(&hmac_sha256(KEY, DATA)),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn hmac_sha512_matches_rfc_4231() {
+        assert_eq!(
+            hex(&hmac_sha512(KEY, DATA)),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+             9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_header_names() {
+        let mut request = Request::new(reqwest::Method::GET, "http://x".parse().unwrap());
+        assert!(matches!(insert(&mut request, "bad name", "v"), Err(Error::InvalidHeaderName(_))));
+        assert!(matches!(insert(&mut request, "x-ok", "a\nb"), Err(Error::InvalidHeaderValue(_))));
+        insert(&mut request, "x-ok", "v").unwrap();
+        assert_eq!(request.headers()["x-ok"], "v");
+    }
+}
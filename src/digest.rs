@@ -0,0 +1,288 @@
+use crate::errors::{Error, HurlResult};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// A Digest challenge from a `WWW-Authenticate` header
+///
+/// Challenges are kept in the session so that later requests can answer
+/// them without another round trip, counting up `nc` each time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Challenge{
+    pub realm: String,
+    pub nonce: String,
+    pub opaque: Option<String>,
+    pub algorithm: String,
+    pub qop: Vec<String>,
+    #[serde(default)]
+    pub nc: u32,
+}
+
+impl Challenge{
+    /// The quality of protection used to answer the challenge, `auth`
+    /// when the server offers it
+    fn qop(&self) -> Option<&'static str>{
+        if self.qop.iter().any(|q| q == "auth"){
+            Some("auth")
+        } else if self.qop.iter().any(|q| q == "auth-int"){
+            Some("auth-int")
+        } else {
+            None
+        }
+    }
+
+    /// Whether the answer covers the request body, which is only the case
+    /// for `qop=auth-int`
+    pub fn needs_body(&self) -> bool{
+        self.qop() == Some("auth-int")
+    }
+}
+
+/// Parse a `WWW-Authenticate` header, if it is a Digest challenge
+pub fn parse_challenge(header: &str) -> Option<Challenge>{
+    let header = header.trim();
+    if header.len() < 7 || !header[..7].eq_ignore_ascii_case("digest "){
+        return None;
+    }
+    let mut realm = None;
+    let mut nonce = None;
+    let mut opaque = None;
+    let mut algorithm = "MD5".to_owned();
+    let mut qop = Vec::new();
+    for (key, value) in parse_params(&header[7..]){
+        match key.to_ascii_lowercase().as_str(){
+            "realm" => realm = Some(value),
+            "nonce" => nonce = Some(value),
+            "opaque" => opaque = Some(value),
+            "algorithm" => algorithm = value.to_ascii_uppercase(),
+            "qop" => {
+                qop = value
+                    .split(',')
+                    .map(|q| q.trim().to_ascii_lowercase())
+                    .filter(|q| !q.is_empty())
+                    .collect()
+            }
+            _ => {}
+        }
+    }
+    Some(Challenge{
+        realm: realm?,
+        nonce: nonce?,
+        opaque,
+        algorithm,
+        qop,
+        nc: 0,
+    })
+}
+
+/// Split `key=value, key="quoted, value"` pairs
+fn parse_params(s: &str) -> Vec<(String, String)>{
+    let mut params = Vec::new();
+    let mut chars = s.chars().peekable();
+    loop{
+        while let Some(c) = chars.peek(){
+            if *c == ',' || c.is_whitespace(){
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let mut key = String::new();
+        for c in chars.by_ref(){
+            if c == '='{
+                break;
+            }
+            key.push(c);
+        }
+        if key.is_empty(){
+            return params;
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'"'){
+            chars.next();
+            while let Some(c) = chars.next(){
+                match c{
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.peek(){
+                if *c == ','{
+                    break;
+                }
+                value.push(*c);
+                chars.next();
+            }
+        }
+        params.push((key.trim().to_owned(), value.trim().to_owned()));
+    }
+}
+
+fn hash(algorithm: &str, data: &[u8]) -> HurlResult<String>{
+    match algorithm.trim_end_matches("-SESS"){
+        "MD5" => Ok(format!("{:x}", md5::compute(data))),
+        "SHA-256" => Ok(format!("{:x}", Sha256::digest(data))),
+        _ => Err(Error::UnsupportedDigestAlgorithm(algorithm.to_owned())),
+    }
+}
+
+/// The `Authorization` header value answering `challenge`
+///
+/// `body` is only needed when the challenge `needs_body`, for
+/// `qop=auth-int`, which is used when the server does not offer plain
+/// `auth`.
+pub fn authorization(
+    challenge: &Challenge,
+    method: &str,
+    uri: &str,
+    username: &str,
+    password: &str,
+    body: Option<&[u8]>,
+) -> HurlResult<String>{
+    let mut cnonce_bytes = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut cnonce_bytes);
+    let cnonce: String = cnonce_bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    answer(challenge, method, uri, username, password, body, &cnonce)
+}
+
+fn answer(
+    challenge: &Challenge,
+    method: &str,
+    uri: &str,
+    username: &str,
+    password: &str,
+    body: Option<&[u8]>,
+    cnonce: &str,
+) -> HurlResult<String>{
+    let algorithm = challenge.algorithm.as_str();
+    let nc = format!("{:08x}", challenge.nc);
+
+    let mut ha1 = hash(
+        algorithm,
+        format!("{}:{}:{}", username, challenge.realm, password).as_bytes(),
+    )?;
+    if algorithm.ends_with("-SESS"){
+        ha1 = hash(
+            algorithm,
+            format!("{}:{}:{}", ha1, challenge.nonce, cnonce).as_bytes(),
+        )?;
+    }
+
+    let qop = challenge.qop();
+    let ha2 = match qop{
+        Some("auth-int") => {
+            let body = body.ok_or(Error::BodyUnavailable)?;
+            let body_hash = hash(algorithm, body)?;
+            hash(algorithm, format!("{}:{}:{}", method, uri, body_hash).as_bytes())?
+        }
+        _ => hash(algorithm, format!("{}:{}", method, uri).as_bytes())?,
+    };
+
+    let response = match qop{
+        Some(qop) => hash(
+            algorithm,
+            format!(
+                "{}:{}:{}:{}:{}:{}",
+                ha1, challenge.nonce, nc, cnonce, qop, ha2
+            )
+            .as_bytes(),
+        )?,
+        None => hash(
+            algorithm,
+            format!("{}:{}:{}", ha1, challenge.nonce, ha2).as_bytes(),
+        )?,
+    };
+
+    let mut header = format!(
+        "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+        quote(username),
+        quote(&challenge.realm),
+        quote(&challenge.nonce),
+        quote(uri),
+        challenge.algorithm,
+        response
+    );
+    if let Some(qop) = qop{
+        header.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", qop, nc, quote(cnonce)));
+    }
+    if let Some(opaque) = &challenge.opaque{
+        header.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+    }
+    Ok(header)
+}
+
+/// Escape `"` and `\` for a quoted string
+fn quote(s: &str) -> String{
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example of RFC 7616 section 3.9.1
+    fn rfc_challenge(algorithm: &str) -> Challenge {
+        let header = format!(
+            "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm={}, \
+             nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+             opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"",
+            algorithm
+        );
+        let mut challenge = parse_challenge(&header).unwrap();
+        challenge.nc = 1;
+        challenge
+    }
+
+    fn rfc_answer(algorithm: &str) -> String {
+        answer(
+            &rfc_challenge(algorithm),
+            "GET",
+            "/dir/index.html",
+            "Mufasa",
+            "Circle of Life",
+            None,
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn answers_rfc_7616_md5() {
+        assert_eq!(
+            rfc_answer("MD5"),
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+             nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", uri=\"/dir/index.html\", \
+             algorithm=MD5, response=\"8ca523f5e9506fed4657c9700eebdbec\", qop=auth, nc=00000001, \
+             cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", \
+             opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""
+        );
+    }
+
+    #[test]
+    fn answers_rfc_7616_sha256() {
+        assert!(rfc_answer("SHA-256")
+            .contains("response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""));
+    }
+
+    #[test]
+    fn needs_the_body_only_for_auth_int() {
+        let mut challenge = rfc_challenge("MD5");
+        assert!(!challenge.needs_body());
+        challenge.qop = vec!["auth-int".to_owned()];
+        assert!(challenge.needs_body());
+        let result = answer(&challenge, "GET", "/", "u", "p", None, "c");
+        assert!(matches!(result, Err(Error::BodyUnavailable)));
+        assert!(answer(&challenge, "GET", "/", "u", "p", Some(b""), "c").is_ok());
+    }
+
+    #[test]
+    fn escapes_quoted_values() {
+        let mut challenge = rfc_challenge("MD5");
+        challenge.realm = "a \"realm\" \\ b".to_owned();
+        let header = answer(&challenge, "GET", "/", "us\"er", "p", None, "c").unwrap();
+        assert!(header.contains("username=\"us\\\"er\""));
+        assert!(header.contains("realm=\"a \\\"realm\\\" \\\\ b\""));
+    }
+}
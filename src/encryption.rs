@@ -0,0 +1,103 @@
+use crate::errors::{Error, HurlResult};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use hmac::Hmac;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::convert::TryInto;
+
+const FORMAT: &str = "hurl-encrypted-v1";
+const ROUNDS: u32 = 100_000;
+
+/// The on-disk form of an encrypted file
+///
+/// The key is derived from the passphrase with PBKDF2-HMAC-SHA256 using
+/// a random salt, and the contents are sealed with XChaCha20-Poly1305 so
+/// that a wrong key and a modified file are both detected.
+#[derive(Serialize, Deserialize)]
+struct Envelope{
+    format: String,
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+pub fn is_encrypted(content: &[u8]) -> bool{
+    serde_json::from_slice::<Envelope>(content)
+        .map(|e| e.format == FORMAT)
+        .unwrap_or(false)
+}
+
+pub fn encrypt(passphrase: &str, plaintext: &[u8]) -> HurlResult<Vec<u8>>{
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 24];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &salt));
+    let data = cipher
+        .encrypt(&XNonce::from(nonce), plaintext)
+        .map_err(|_| Error::EncryptionFailed)?;
+    let envelope = Envelope{
+        format: FORMAT.to_owned(),
+        salt: base64::encode(salt),
+        nonce: base64::encode(nonce),
+        data: base64::encode(&data),
+    };
+    serde_json::to_vec(&envelope).map_err(From::from)
+}
+
+pub fn decrypt(passphrase: &str, content: &[u8]) -> HurlResult<Vec<u8>>{
+    let envelope: Envelope =
+        serde_json::from_slice(content).map_err(|_| Error::CorruptEncryptedFile)?;
+    let salt = base64::decode(&envelope.salt).map_err(|_| Error::CorruptEncryptedFile)?;
+    let nonce = base64::decode(&envelope.nonce).map_err(|_| Error::CorruptEncryptedFile)?;
+    let data = base64::decode(&envelope.data).map_err(|_| Error::CorruptEncryptedFile)?;
+    if envelope.format != FORMAT{
+        return Err(Error::CorruptEncryptedFile);
+    }
+    let nonce: [u8; 24] = nonce.as_slice().try_into().map_err(|_| Error::CorruptEncryptedFile)?;
+
+    let cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &salt));
+    // Authentication fails for both a wrong key and tampered data, the
+    // former being far more likely
+    cipher
+        .decrypt(&XNonce::from(nonce), data.as_ref())
+        .map_err(|_| Error::WrongSessionKey)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Key{
+    let mut key = Key::default();
+    // This cannot fail, HMAC takes keys of any length
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, ROUNDS, &mut key);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let sealed = encrypt("key", b"{\"name\":\"s\"}").unwrap();
+        assert!(is_encrypted(&sealed));
+        assert!(!is_encrypted(b"{\"name\":\"s\"}"));
+        assert_eq!(decrypt("key", &sealed).unwrap(), b"{\"name\":\"s\"}");
+    }
+
+    #[test]
+    fn rejects_wrong_keys_and_changes() {
+        let sealed = encrypt("key", b"secret").unwrap();
+        assert!(matches!(decrypt("other", &sealed), Err(Error::WrongSessionKey)));
+
+        let mut envelope: Envelope = serde_json::from_slice(&sealed).unwrap();
+        let mut data = base64::decode(&envelope.data).unwrap();
+        data[0] ^= 1;
+        envelope.data = base64::encode(&data);
+        let changed = serde_json::to_vec(&envelope).unwrap();
+        assert!(matches!(decrypt("key", &changed), Err(Error::WrongSessionKey)));
+        assert!(matches!(decrypt("key", b"{}"), Err(Error::CorruptEncryptedFile)));
+    }
+}
@@ -1,5 +1,6 @@
 use std::fmt;
 
+#[allow(clippy::enum_variant_names)]
 pub enum Error{
     ParameterMissingSeparator(String),
     MissingUrlAndCommand,
@@ -12,11 +13,58 @@ pub enum Error{
     IO(std::io::ErrorKind),
     UrlParseError(reqwest::UrlError),
     SyntaxLoadError(&'static str),
+    RequestFileSyntax(usize, String),
+    RequestNotFound(String),
+    BodyWithDataParameters,
+    AssertionFailed(Vec<crate::assertions::Failure>),
+    InvalidCapture(String),
+    CaptureNotFound(String),
+    CaptureWithoutSession,
+    ConflictingOptions(&'static str, &'static str),
+    UndefinedVariable(String),
+    InvalidEnvFile(std::path::PathBuf, usize),
+    UnknownProfile(String),
+    ConfigParse(std::path::PathBuf, Option<usize>, String),
+    EditorFailed(String),
+    UnknownSavedRequest(String),
+    InvalidMethod(String),
+    SessionNotFound(String, String),
+    SessionExists(String),
+    SessionKeyRequired,
+    SessionsNotEncrypted(usize),
+    WrongSessionKey,
+    CorruptEncryptedFile,
+    EncryptionFailed,
+    InvalidKeyringRef(String),
+    KeyringEntryNotFound(String),
+    Keyring(String),
+    InvalidNetrc(std::path::PathBuf, String),
+    UnknownAuthType(String),
+    MissingCredentials(crate::app::AuthType),
+    InvalidHeaderName(String),
+    InvalidHeaderValue(String),
+    DigestChallengeMissing,
+    UnsupportedDigestAlgorithm(String),
+    UnknownOAuthProvider(String),
+    OAuth(String),
+    AwsCredentialsMissing,
+    AwsOptionMissing(&'static str),
+    UnknownSigningScheme(String),
+    UnknownSigningPlaceholder(String),
+    BodyUnavailable,
+    Jwt(String),
+    InvalidPartModifier{ param: String, position: usize, message: String },
+    FileNotUtf8(String),
+    UnknownQueryArray(String),
+    InvalidQueryFile(std::path::PathBuf, String),
+    ValueSource(String, String),
+    BodyNotAllowed(String),
+    HeaderAlwaysSent(String),
 }
 
 pub type HurlResult<T> = Result<T, Error>;
 
-impl ftm::Display for Error{
+impl fmt::Display for Error{
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
         match self{
             Error::ParameterMissingSeparator(s) => {
@@ -25,12 +73,127 @@ impl ftm::Display for Error{
             Error::MissingUrlAndCommand => write!(f, "Must specify a url or a commande!"), 
             Error::NotFromButHasFormFile => write!(f, " Cannot have a form file 'key@filename' unless --form option on set"),
             Error::ClientSerialization => write!(f, "Serializing the request/ responde failed"),
+            Error::ClientTimeout => write!(f, "The request timed out"),
             Error::ClientWithStatus(status) => write!(f , " Got status code: {}",status),
             Error::ClientOther => write!(f, "Unknown client error"),
             Error::SerdeJson(c) => write!(f, " JSON error : {:?}", c),
             Error::IO(k) => write!(f, "IO Error: {:?}", k),
             Error::UrlParseError(e) => write!(f, " URL Parsing error: {}", e),
             Error::SyntaxLoadError(typ) => write!(f, "Error loading syntax for {}", typ),
+            Error::RequestFileSyntax(line, msg) => {
+                write!(f, "Invalid request file at line {}: {}", line, msg)
+            }
+            Error::RequestNotFound(name) => write!(f, "No request named '{}' in file", name),
+            Error::BodyWithDataParameters => {
+                write!(f, "Cannot combine a request body with data parameters")
+            }
+            Error::AssertionFailed(failures) => {
+                write!(f, "{} expectation(s) failed", failures.len())?;
+                for failure in failures.iter(){
+                    write!(f, "\n{}", failure)?;
+                }
+                Ok(())
+            }
+            Error::InvalidCapture(s) => write!(
+                f,
+                "Invalid capture '{}', expected name=.json.path, name=header:Name or name=cookie:name",
+                s
+            ),
+            Error::CaptureNotFound(name) => {
+                write!(f, "Value to capture into '{}' not found in response", name)
+            }
+            Error::CaptureWithoutSession => write!(f, "Capturing values requires a --session"),
+            Error::ConflictingOptions(a, b) => write!(f, "{} cannot be used with {}", a, b),
+            Error::UndefinedVariable(name) => write!(f, "Undefined variable: {}", name),
+            Error::InvalidEnvFile(path, line) => write!(
+                f,
+                "Invalid env file {} at line {}: expected KEY=VALUE",
+                path.display(),
+                line
+            ),
+            Error::UnknownProfile(name) => {
+                write!(f, "No profile named '{}' in the configuration file", name)
+            }
+            Error::ConfigParse(path, Some(line), msg) => write!(
+                f,
+                "Invalid configuration file {} at line {}: {}",
+                path.display(),
+                line,
+                msg
+            ),
+            Error::ConfigParse(path, None, msg) => {
+                write!(f, "Invalid configuration file {}: {}", path.display(), msg)
+            }
+            Error::EditorFailed(editor) => write!(f, "Editor '{}' exited with an error", editor),
+            Error::UnknownSavedRequest(name) => {
+                write!(f, "No saved request named '{}' in the configuration file", name)
+            }
+            Error::InvalidMethod(method) => write!(f, "Invalid HTTP method: {}", method),
+            Error::SessionNotFound(name, host) => {
+                write!(f, "No session named '{}' for {}", name, host)
+            }
+            Error::SessionExists(name) => write!(f, "A session named '{}' already exists", name),
+            Error::SessionKeyRequired => write!(
+                f,
+                "The session is encrypted, set HURL_SESSION_KEY or use --ask-session-key"
+            ),
+            Error::SessionsNotEncrypted(n) => write!(f, "{} session files could not be encrypted", n),
+            Error::WrongSessionKey => write!(f, "Wrong session key, or the session file was modified"),
+            Error::CorruptEncryptedFile => write!(f, "The encrypted session file is corrupted"),
+            Error::EncryptionFailed => write!(f, "Encrypting the session failed"),
+            Error::InvalidKeyringRef(s) => {
+                write!(f, "Invalid keyring reference '{}', expected keyring:service/user", s)
+            }
+            Error::KeyringEntryNotFound(id) => write!(f, "No keyring entry for {}", id),
+            Error::Keyring(msg) => write!(f, "Keyring error: {}", msg),
+            Error::InvalidNetrc(path, msg) => write!(f, "Invalid netrc file {}: {}", path.display(), msg),
+            Error::UnknownAuthType(s) => write!(f, "Unknown authentication type: {}", s),
+            Error::MissingCredentials(auth_type) => {
+                write!(f, "No credentials given for {} authentication", auth_type)
+            }
+            Error::InvalidHeaderName(n) => write!(f, "Invalid header name: {}", n),
+            Error::InvalidHeaderValue(v) => write!(f, "Invalid header value: {}", v),
+            Error::DigestChallengeMissing => {
+                write!(f, "The server did not send a Digest challenge with its 401 response")
+            }
+            Error::UnsupportedDigestAlgorithm(a) => write!(f, "Unsupported Digest algorithm: {}", a),
+            Error::UnknownOAuthProvider(name) => {
+                write!(f, "No OAuth provider named '{}' in the configuration file", name)
+            }
+            Error::OAuth(msg) => write!(f, "OAuth error: {}", msg),
+            Error::AwsCredentialsMissing => write!(
+                f,
+                "No AWS credentials in AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY or ~/.aws/credentials"
+            ),
+            Error::AwsOptionMissing(option) => write!(f, "aws-sigv4 authentication needs {}", option),
+            Error::UnknownSigningScheme(name) => {
+                write!(f, "No signing scheme named '{}' in the configuration file", name)
+            }
+            Error::UnknownSigningPlaceholder(p) => {
+                write!(f, "Unknown placeholder {{{}}} in signing template", p)
+            }
+            Error::BodyUnavailable => write!(f, "The request body from stdin cannot be read again"),
+            Error::Jwt(msg) => write!(f, "JWT error: {}", msg),
+            Error::BodyNotAllowed(method) => write!(f, "{} requests cannot have a body", method),
+            Error::HeaderAlwaysSent(name) => write!(
+                f,
+                "The {} header is always sent and cannot be unset, give it a value to replace it",
+                name
+            ),
+            Error::ValueSource(source, msg) => write!(f, "Cannot read value {}: {}", source, msg),
+            Error::UnknownQueryArray(s) => write!(f, "Unknown query array style: {}", s),
+            Error::InvalidQueryFile(path, msg) => {
+                write!(f, "Invalid query file {}: {}", path.display(), msg)
+            }
+            Error::FileNotUtf8(filename) => write!(
+                f,
+                "{} is not UTF-8 text, use key=@@{} to send it base64 encoded",
+                filename, filename
+            ),
+            Error::InvalidPartModifier{ param, position, message } => {
+                let column = param.get(..*position).map_or(0, |p|p.chars().count());
+                write!(f, "Invalid form file parameter: {}\n  {}\n  {}^", message, param, " ".repeat(column))
+            }
         }
     }
 }
@@ -54,9 +217,9 @@ impl std::error::Error for Error {
     }
 }
 
-impl From<reqwest:Error> for Error{
+impl From<reqwest::Error> for Error{
     #[inline]
-    fn from(err: request::Error) -> Error{
+    fn from(err: reqwest::Error) -> Error{
         if err.is_serialization(){
             return Error::ClientSerialization;
         }
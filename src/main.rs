@@ -1,21 +1,47 @@
-use structopt::StructOpt;
 use heck::TitleCase;
 use log::trace;
 mod app;
+mod assertions;
+mod aws;
 mod client;
 mod errors;
 mod config;
+mod credentials;
+mod crypto;
+mod digest;
+mod jwt;
 mod directories;
+mod netrc;
+mod oauth;
+mod payload;
+mod encryption;
+mod request_file;
 mod session;
+mod signing;
+mod source;
+mod upload;
 use errors::HurlResult;
 type OrderedJson = std::collections::BTreeMap<String, serde_json::Value>;
 use syntect::highlighting::Theme;
 use syntect::parsing::SyntaxSet;
-mod systax;
+mod syntax;
+mod time;
+mod variables;
 fn main() -> HurlResult<()> {
-    let mut app = app::App::from_args();
-    app::validate()?;
-    app::process_config_file();
+    let args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    if let Some(env_file) = app::App::env_file_arg(&args){
+        variables::load_env_file(&env_file)?;
+    }
+    let mut app = app::App::from_args_with_methods();
+    if let Some(app::Command::Config(ref cmd)) = app.cmd{
+        return config::run_command(&app, cmd);
+    }
+    app.process_config_file()?;
+    app.resolve_commands()?;
+    app.resolve_saved_request()?;
+    app.load_query_file()?;
+    app.validate()?;
+    app.resolve_session_key()?;
 
     if let Some(level) = app.log_level(){
         std::env::set_var("RUST_LOG", format!("hurl={}", level));
@@ -23,30 +49,53 @@ fn main() -> HurlResult<()> {
     }
 
     let (ss,ts) = syntax::build()?;
-    let theme = &ts.themes["Solarized(dark)"];
+    let theme = &ts.themes["Solarized (dark)"];
+
+    if let Some(app::Command::Auth(ref cmd)) = app.cmd{
+        return credentials::run_command(&app, cmd);
+    }
+
+    if let Some(app::Command::Jwt(ref cmd)) = app.cmd{
+        return jwt::run_command(&app, cmd);
+    }
+
+    if let Some(app::Command::Session(ref cmd)) = app.cmd{
+        return session::run_command(&app, cmd);
+    }
+
+    if let Some(app::Command::Run(ref run)) = app.cmd{
+        return run_file(&app, &ss, theme, run);
+    }
 
     let mut session = app
                 .session
                 .as_ref()
-                .map(|name| session::Sesion::get_or_crate(&app, name.clone(), app.host()));
+                .map(|name| session::Session::get_or_create(&app, name.clone(), app.host()))
+                .transpose()?;
 
 
     match app.cmd{
-        Some(ref method) => {
+        Some(app::Command::Method(ref method)) => {
             let resp = client::perform_method(&app, method, &mut session)?;
             handle_response(&app, &ss, theme, resp, &mut session)
         }
         None =>{
             let url = app.url.take().unwrap();
             let has_data = app.parameters.iter().any(|p|p.is_data());
-            let method = if has_data{
-                reqwest::Method::POST
-            } else {
-                reqwest::Method::GET
+            let method = match app.method.take().or_else(|| app.saved_method.take()){
+                Some(method) => method,
+                None if app.upload.is_some() => reqwest::Method::PUT,
+                None if has_data => reqwest::Method::POST,
+                None => reqwest::Method::GET,
             };
-            let resp = client::perform(&app, method, &mut session, &url, &app.parametrers)?;
-            handel_response(&app, &ss, theme, resp, &mut session)
+            let resp = client::perform(&app, method, &mut session, &url, &app.parameters)?;
+            handle_response(&app, &ss, theme, resp, &mut session)
             }
+        Some(app::Command::Run(_))
+        | Some(app::Command::Config(_))
+        | Some(app::Command::Session(_))
+        | Some(app::Command::Auth(_))
+        | Some(app::Command::Jwt(_)) => unreachable!(),
 
 
             
@@ -55,14 +104,51 @@ fn main() -> HurlResult<()> {
         }
     }
 
+fn run_file(
+    app: &app::App,
+    ss: &SyntaxSet,
+    theme: &Theme,
+    run: &app::RunData,
+) -> HurlResult<()>{
+    let entries = request_file::read_file(&run.file)?;
+    let entries = request_file::select(entries, run.name.as_deref())?;
+
+    // One session is shared by every request in the file and keyed
+    // on the scheme, host and port of the first one.
+    let mut session = match (&app.session, entries.first()){
+        (Some(name), Some(first)) => Some(session::Session::get_or_create(
+            app,
+            name.clone(),
+            session::host_key(app, &first.url),
+        )?),
+        _ => None,
+    };
+
+    for entry in entries.iter(){
+        println!("### {}", entry.display_name());
+        let resp = client::perform_with_body(
+            app,
+            entry.method.clone(),
+            &mut session,
+            &entry.url,
+            &entry.parameters(),
+            entry.body.clone(),
+        )?;
+        handle_response(app, ss, theme, resp, &mut session)?;
+        println!();
+    }
+    Ok(())
+}
+
 fn handle_response(
     app: &app::App,
     ss: &SyntaxSet,
     theme: &Theme,
     mut resp: reqwest::Response,
-    session: &mut Option<session::Sesion>,
+    session: &mut Option<session::Session>,
 ) -> HurlResult<()>{
     let status = resp.status();
+    let response_headers = resp.headers().clone();
     let mut s = format!(
         "{:?},{},{}\n",
         resp.version(),
@@ -71,7 +157,7 @@ fn handle_response(
     );
     let mut headers = Vec::new();
     for (key, value) in resp.headers().iter(){
-        let nice_key = key.as_str().to_tatle_case().replace(" ","-");
+        let nice_key = key.as_str().to_title_case().replace(" ","-");
         headers.push(format!(
             "{} : {}",
             nice_key,
@@ -85,43 +171,61 @@ fn handle_response(
     }; 
     headers.push(format!("Content-Length: {}", content_lenght));
     headers.sort();
-    s.push_str(&(&headers[..]).join("\n"));
-    highlighting(ss, theme, "HTTP", &s);
-    println!("{}", s);
-    println!("");
+    s.push_str(&headers[..].join("\n"));
+    highlight_string(ss, theme, "HTTP", &s);
+    println!();
     let result_json: serde_json::Result<OrderedJson> = serde_json::from_str(&result);
     match result_json {
         Ok(result_value) => {
             let result_str = serde_json::to_string_pretty(&result_value)?;
             highlight_string(ss, theme, "JSON", &result_str);
-            println!("{}", result_str);
         }
         Err(e) => {
             trace!("Falued to parse result to JSON: {}", e);
             println!("{}", result);
         }
         }
+        // Nothing is captured from a response which fails the
+        // expectations, but the session is saved either way.
+        let mut outcome = if assertions::has_expectations(app){
+            assertions::check(app, status, &response_headers, &result)
+        } else {
+            Ok(())
+        };
+        if outcome.is_ok() && !app.capture.is_empty(){
+            let cookies: Vec<(String, String)> = resp
+                .cookies()
+                .map(|c|(c.name().to_owned(), c.value().to_owned()))
+                .collect();
+            outcome = variables::capture(&app.capture, &response_headers, &cookies, &result).map(|captured|{
+                if let Some(s) = session.as_mut(){
+                    for (name, value) in captured{
+                        s.set_variable(name, value);
+                    }
+                }
+            });
+        }
         if !app.read_only{
             if let Some(s) = session{
                 s.update_with_response(&resp);
                 s.save(app)?;
             }
         }
-        Ok(())
+        outcome
     }
 
-fn highlight_string(ss: &SyntexSet, theme: &Theme, syntax: &str, string: &str){
+fn highlight_string(ss: &SyntaxSet, theme: &Theme, syntax: &str, string: &str){
 
     use syntect::easy::HighlightLines;
     use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
 
     let syn = ss
         .find_syntax_by_name(syntax)
-        .expect(&format!("{} syntax should exist ", syntax));
+        .unwrap_or_else(|| ss.find_syntax_plain_text());
     let mut h = HighlightLines::new(syn, theme);
     for line in LinesWithEndings::from(string){
-        let regions = h.highlight(line, &ss);
-        print!("{}", as_24_bit_terminal_escaped(&regions[], false));
+        let regions = h.highlight(line, ss);
+        print!("{}", as_24_bit_terminal_escaped(&regions[..], false));
     }
     println!("\x1b[0m")
 }
@@ -0,0 +1,179 @@
+use crate::errors::{Error, HurlResult};
+use log::trace;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct Credentials{
+    pub login: String,
+    pub password: String,
+}
+
+/// Find the credentials for `host` in a .netrc file
+///
+/// Without an explicit file `$NETRC` or `~/.netrc` is used, and it not
+/// existing is not an error. A `machine` entry for the host takes
+/// precedence over the `default` entry.
+pub fn lookup(file: Option<&PathBuf>, host: &str) -> HurlResult<Option<Credentials>>{
+    let path = match file{
+        Some(path) => path.clone(),
+        None => match default_path(){
+            Some(path) => path,
+            None => return Ok(None),
+        },
+    };
+    let content = match fs::read_to_string(&path){
+        Ok(content) => content,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound && file.is_none() => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    trace!("Looking up {} in {}", host, path.display());
+    parse(&path, &content, host)
+}
+
+fn default_path() -> Option<PathBuf>{
+    env::var_os("NETRC")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|d| d.join(".netrc")))
+}
+
+fn parse(path: &Path, content: &str, host: &str) -> HurlResult<Option<Credentials>>{
+    let mut default = None;
+    let mut lines = content.lines();
+    let mut tokens: Vec<&str> = Vec::new();
+
+    // Macro definitions run until the next blank line and are skipped
+    while let Some(line) = lines.next(){
+        for word in line.split_whitespace(){
+            if word.starts_with('#'){
+                break;
+            }
+            if word == "macdef"{
+                for l in lines.by_ref(){
+                    if l.trim().is_empty(){
+                        break;
+                    }
+                }
+                break;
+            }
+            tokens.push(word);
+        }
+    }
+
+    let mut iter = tokens.into_iter();
+    let mut current: Option<(bool, Option<String>, Option<String>)> = None;
+    let mut entries = Vec::new();
+    while let Some(token) = iter.next(){
+        match token{
+            "machine" => {
+                entries.extend(current.take());
+                let name = iter.next().ok_or_else(|| invalid(path, "machine without a name"))?;
+                current = Some((name.eq_ignore_ascii_case(host), None, None));
+            }
+            "default" => {
+                entries.extend(current.take());
+                default = Some(entries.len());
+                current = Some((false, None, None));
+            }
+            "login" | "password" | "account" | "port" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| invalid(path, &format!("{} without a value", token)))?;
+                let entry = current
+                    .as_mut()
+                    .ok_or_else(|| invalid(path, &format!("{} outside of a machine", token)))?;
+                match token{
+                    "login" => entry.1 = Some(value.to_owned()),
+                    "password" => entry.2 = Some(value.to_owned()),
+                    _ => {}
+                }
+            }
+            other => return Err(invalid(path, &format!("unexpected '{}'", other))),
+        }
+    }
+    entries.extend(current.take());
+
+    let found = entries
+        .iter()
+        .position(|(matches, _, _)| *matches)
+        .or(default)
+        .and_then(|i| entries.get(i));
+    Ok(match found{
+        Some((_, Some(login), password)) => Some(Credentials{
+            login: login.clone(),
+            password: password.clone().unwrap_or_default(),
+        }),
+        _ => None,
+    })
+}
+
+fn invalid(path: &Path, msg: &str) -> Error{
+    Error::InvalidNetrc(path.to_owned(), msg.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(content: &str, host: &str) -> Option<(String, String)> {
+        parse(Path::new(".netrc"), content, host)
+            .unwrap()
+            .map(|c| (c.login, c.password))
+    }
+
+    fn pair(login: &str, password: &str) -> Option<(String, String)> {
+        Some((login.to_owned(), password.to_owned()))
+    }
+
+    #[test]
+    fn finds_machines_before_the_default() {
+        let content = "\
+# a comment
+machine a.example.com login ann password one
+machine B.example.com
+    login bob # a trailing comment
+    account acct
+    password two
+default login anonymous password guest
+";
+        assert_eq!(find(content, "a.example.com"), pair("ann", "one"));
+        assert_eq!(find(content, "b.example.com"), pair("bob", "two"));
+        assert_eq!(find(content, "c.example.com"), pair("anonymous", "guest"));
+        assert_eq!(find("machine a login ann", "a"), pair("ann", ""));
+        assert_eq!(find("machine a login ann", "b"), None);
+        assert_eq!(find("machine a password one", "a"), None);
+    }
+
+    #[test]
+    fn skips_macro_definitions() {
+        let content = "\
+machine a login ann password one
+macdef init
+machine evil login mallory password bad
+cd /pub
+
+machine b login bob password two
+";
+        assert_eq!(find(content, "evil"), None);
+        assert_eq!(find(content, "b"), pair("bob", "two"));
+    }
+
+    #[test]
+    fn rejects_bad_tokens() {
+        for bad in &["machine", "machine a login", "login ann", "machine a user ann"] {
+            assert!(
+                matches!(parse(Path::new(".netrc"), bad, "a"), Err(Error::InvalidNetrc(..))),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn requires_an_explicit_file_to_exist() {
+        let missing = env::temp_dir().join("hurl-missing-netrc");
+        assert!(lookup(Some(&missing), "a").is_err());
+    }
+}
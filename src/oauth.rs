@@ -0,0 +1,519 @@
+use crate::app::App;
+use crate::client;
+use crate::credentials;
+use crate::errors::{Error, HurlResult};
+use crate::session::{make_safe_pathname, Session};
+use crate::time::now;
+use log::{trace, warn};
+use rand::RngCore;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Tokens are refreshed this many seconds before they expire
+const EXPIRY_MARGIN: u64 = 30;
+/// How long `hurl auth login` waits for the redirect
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// An OAuth2 authorization server, configured as `[oauth.NAME]`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Provider{
+    pub token_url: String,
+    pub client_id: String,
+    /// May be a `keyring:service/user` reference
+    pub client_secret: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub grant: Grant,
+    /// The authorization endpoint, for `hurl auth login`
+    pub authorization_url: Option<String>,
+    /// The port of the local callback listener, random by default
+    pub redirect_port: Option<u16>,
+    pub username: Option<String>,
+    /// May be a `keyring:service/user` reference
+    pub password: Option<String>,
+    /// The URL whose session `hurl auth login` stores the tokens in when
+    /// it is not given one
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Grant{
+    #[default]
+    ClientCredentials,
+    Password,
+    /// Interactive login with `hurl auth login`
+    AuthorizationCode,
+}
+
+/// Tokens issued by a provider, cached in the session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tokens{
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// Seconds since the Unix epoch
+    pub expires_at: Option<u64>,
+}
+
+impl Tokens{
+    fn is_expired(&self) -> bool{
+        match self.expires_at{
+            Some(expires_at) => now() + EXPIRY_MARGIN >= expires_at,
+            None => false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse{
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse{
+    error: String,
+    error_description: Option<String>,
+}
+
+pub fn provider<'a>(app: &'a App, name: &str) -> HurlResult<&'a Provider>{
+    app.oauth_providers
+        .get(name)
+        .ok_or_else(|| Error::UnknownOAuthProvider(name.to_owned()))
+}
+
+/// An access token for the provider `name`
+///
+/// The token cached in the session is used until it expires, after which
+/// it is refreshed with the refresh token if there is one, or otherwise
+/// requested again. `force_refresh` skips the cached token, for when the
+/// server rejected it.
+pub fn access_token(
+    app: &App,
+    client: &Client,
+    name: &str,
+    session: &mut Option<Session>,
+    force_refresh: bool,
+) -> HurlResult<String>{
+    let provider = provider(app, name)?;
+    let cached = session.as_ref().and_then(|s|s.oauth_tokens(name)).cloned();
+    if let Some(tokens) = &cached{
+        if !force_refresh && !tokens.is_expired(){
+            trace!("Using cached OAuth token for {}", name);
+            return Ok(tokens.access_token.clone());
+        }
+    }
+
+    let refresh_token = cached.and_then(|t|t.refresh_token);
+    let tokens = match &refresh_token{
+        Some(refresh_token) => {
+            trace!("Refreshing OAuth token for {}", name);
+            let params = vec![
+                ("grant_type", "refresh_token".to_owned()),
+                ("refresh_token", refresh_token.clone()),
+            ];
+            match request_tokens(client, provider, params){
+                Ok(tokens) => tokens,
+                Err(e) => {
+                    warn!("Refreshing the token failed, requesting a new one: {}", e);
+                    grant(client, provider)?
+                }
+            }
+        }
+        None => grant(client, provider)?,
+    };
+    let tokens = Tokens{
+        refresh_token: tokens.refresh_token.or(refresh_token),
+        ..tokens
+    };
+
+    let access_token = tokens.access_token.clone();
+    if let Some(s) = session{
+        s.set_oauth_tokens(name, tokens);
+    }
+    Ok(access_token)
+}
+
+fn grant(client: &Client, provider: &Provider) -> HurlResult<Tokens>{
+    trace!("Requesting OAuth token with {:?} grant", provider.grant);
+    let mut params = Vec::new();
+    match provider.grant{
+        Grant::ClientCredentials => {
+            params.push(("grant_type", "client_credentials".to_owned()));
+        }
+        Grant::Password => {
+            let username = provider
+                .username
+                .clone()
+                .ok_or_else(|| Error::OAuth("the password grant needs a username".to_owned()))?;
+            let password = provider
+                .password
+                .as_ref()
+                .ok_or_else(|| Error::OAuth("the password grant needs a password".to_owned()))?;
+            params.push(("grant_type", "password".to_owned()));
+            params.push(("username", username));
+            params.push(("password", credentials::resolve_token(password)?));
+        }
+        Grant::AuthorizationCode => {
+            return Err(Error::OAuth(
+                "the session has no valid token, log in with `hurl auth login`".to_owned(),
+            ))
+        }
+    }
+    if !provider.scopes.is_empty(){
+        params.push(("scope", provider.scopes.join(" ")));
+    }
+    request_tokens(client, provider, params)
+}
+
+/// POST a token request, authenticating the client with HTTP basic
+/// authentication when it has a secret
+pub fn request_tokens(
+    client: &Client,
+    provider: &Provider,
+    mut params: Vec<(&str, String)>,
+) -> HurlResult<Tokens>{
+    let mut builder = client.post(&provider.token_url);
+    match &provider.client_secret{
+        Some(secret) => {
+            let secret = credentials::resolve_token(secret)?;
+            builder = builder.basic_auth(&provider.client_id, Some(secret));
+        }
+        None => params.push(("client_id", provider.client_id.clone())),
+    }
+    let mut resp = builder.form(&params).send()?;
+    let body = resp.text()?;
+    if !resp.status().is_success(){
+        let message = match serde_json::from_str::<ErrorResponse>(&body){
+            Ok(e) => match e.error_description{
+                Some(d) => format!("{}: {}", e.error, d),
+                None => e.error,
+            },
+            Err(_) => format!("token endpoint returned {}", resp.status()),
+        };
+        return Err(Error::OAuth(message));
+    }
+    let token: TokenResponse = serde_json::from_str(&body)?;
+    Ok(Tokens{
+        access_token: token.access_token,
+        refresh_token: token.refresh_token,
+        expires_at: token.expires_in.map(|e|now().saturating_add(e)),
+    })
+}
+
+/// Log in with the authorization code flow and PKCE
+///
+/// A listener on localhost receives the redirect from the authorization
+/// server, and the tokens are stored in the session used with `url`, the
+/// url of the provider or the base URL.
+pub fn login(app: &App, name: &str, url: Option<&str>) -> HurlResult<()>{
+    let provider = provider(app, name)?;
+    let url = url
+        .or(provider.url.as_deref())
+        .or(app.base_url.as_deref())
+        .ok_or_else(|| Error::OAuth(format!("give the URL to log in for, or a url for provider {}", name)))?;
+    let session_name = app
+        .session
+        .clone()
+        .ok_or_else(|| Error::OAuth("a --session is needed to store the tokens".to_owned()))?;
+    let authorization_url = provider.authorization_url.as_ref().ok_or_else(|| {
+        Error::OAuth(format!("provider {} has no authorization_url", name))
+    })?;
+
+    let listener = TcpListener::bind(("127.0.0.1", provider.redirect_port.unwrap_or(0)))?;
+    let redirect_uri = format!("http://127.0.0.1:{}/callback", listener.local_addr()?.port());
+    let verifier = random_string(32);
+    let challenge = base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD);
+    let state = random_string(16);
+
+    let mut auth_url = Url::parse(authorization_url)?;
+    auth_url
+        .query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("redirect_uri", &redirect_uri)
+        .append_pair("code_challenge", &challenge)
+        .append_pair("code_challenge_method", "S256")
+        .append_pair("state", &state);
+    if !provider.scopes.is_empty(){
+        auth_url.query_pairs_mut().append_pair("scope", &provider.scopes.join(" "));
+    }
+    println!("Open this URL in a browser to log in:\n\n{}\n", auth_url);
+    println!("Waiting for the redirect to {}", redirect_uri);
+
+    let code = receive_callback(&listener, &state, LOGIN_TIMEOUT)?;
+
+    trace!("Exchanging authorization code for tokens");
+    let client = client::build_client(app)?;
+    let tokens = request_tokens(
+        &client,
+        provider,
+        vec![
+            ("grant_type", "authorization_code".to_owned()),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("code_verifier", verifier),
+        ],
+    )?;
+
+    let mut session = Session::get_or_create(app, session_name.clone(), make_safe_pathname(url))?;
+    session.set_oauth_tokens(name, tokens);
+    session.save(app)?;
+    println!("Logged in to {}, tokens stored in session {}", name, session_name);
+    Ok(())
+}
+
+/// Accept connections until the redirect to /callback with `state`
+/// arrives and return its authorization code
+///
+/// Redirects with another state are answered with an error and ignored,
+/// as they do not come from this login.
+fn receive_callback(listener: &TcpListener, state: &str, timeout: Duration) -> HurlResult<String>{
+    let deadline = Instant::now() + timeout;
+    listener.set_nonblocking(true)?;
+    loop{
+        let mut stream = match listener.accept(){
+            Ok((stream, _)) => stream,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline{
+                    return Err(Error::OAuth("timed out waiting for the redirect".to_owned()));
+                }
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let mut request_line = String::new();
+        if BufReader::new(&stream).read_line(&mut request_line).is_err(){
+            continue;
+        }
+        let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+        trace!("Callback listener received a request for {}", target.split('?').next().unwrap_or(""));
+        if !target.starts_with("/callback"){
+            respond(&mut stream, "404 Not Found", "Not found.")?;
+            continue;
+        }
+        let url = Url::parse(&format!("http://127.0.0.1{}", target))?;
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        if params.get("state").map(String::as_str) != Some(state){
+            respond(&mut stream, "400 Bad Request", "The state of the redirect does not match.")?;
+            continue;
+        }
+        if let Some(error) = params.get("error"){
+            respond(&mut stream, "400 Bad Request", "Logging in failed, see the terminal.")?;
+            let message = match params.get("error_description"){
+                Some(d) => format!("authorization failed: {}: {}", error, d),
+                None => format!("authorization failed: {}", error),
+            };
+            return Err(Error::OAuth(message));
+        }
+        return match params.get("code"){
+            Some(code) => {
+                respond(&mut stream, "200 OK", "Logged in, you can close this window.")?;
+                Ok(code.clone())
+            }
+            None => {
+                respond(&mut stream, "400 Bad Request", "The redirect has no code.")?;
+                Err(Error::OAuth("the redirect has no code".to_owned()))
+            }
+        };
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, message: &str) -> HurlResult<()>{
+    let body = format!("<html><body>{}</body></html>", message);
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+fn random_string(bytes: usize) -> String{
+    let mut buf = vec![0u8; bytes];
+    rand::thread_rng().fill_bytes(&mut buf);
+    base64::encode_config(&buf, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use structopt::StructOpt;
+
+    fn get(port: u16, target: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    /// Answer one token request with `response`, returning the request
+    fn token_endpoint(response: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let url = format!("http://127.0.0.1:{}/token", listener.local_addr().unwrap().port());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            )
+            .unwrap();
+            request
+        });
+        (url, server)
+    }
+
+    fn provider(token_url: String, grant: Grant) -> Provider {
+        Provider {
+            token_url,
+            client_id: "cli".to_owned(),
+            client_secret: None,
+            scopes: vec!["read".to_owned(), "write".to_owned()],
+            grant,
+            authorization_url: None,
+            redirect_port: None,
+            username: Some("ann".to_owned()),
+            password: Some("pw".to_owned()),
+            url: None,
+        }
+    }
+
+    #[test]
+    fn requests_client_credentials() {
+        let (url, server) = token_endpoint(r#"{"access_token":"a1","expires_in":60}"#);
+        let tokens = grant(&Client::new(), &provider(url, Grant::ClientCredentials)).unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /token "));
+        assert!(request.ends_with("grant_type=client_credentials&scope=read+write&client_id=cli"));
+        assert_eq!(tokens.access_token, "a1");
+        assert!(tokens.refresh_token.is_none());
+        assert!(tokens.expires_at.unwrap() >= now() + 60);
+    }
+
+    #[test]
+    fn requests_tokens_with_a_password() {
+        let (url, server) = token_endpoint(r#"{"access_token":"a2","refresh_token":"r2"}"#);
+        let mut provider = provider(url, Grant::Password);
+        provider.client_secret = Some("shh".to_owned());
+        let tokens = grant(&Client::new(), &provider).unwrap();
+        let request = server.join().unwrap();
+        assert!(request.contains("authorization: Basic Y2xpOnNoaA==\r\n"));
+        assert!(request.ends_with("grant_type=password&username=ann&password=pw&scope=read+write"));
+        assert_eq!(tokens.access_token, "a2");
+        assert_eq!(tokens.refresh_token.as_deref(), Some("r2"));
+        assert!(tokens.expires_at.is_none());
+    }
+
+    #[test]
+    fn refreshes_expired_tokens() {
+        let (url, server) = token_endpoint(r#"{"access_token":"a3"}"#);
+        let mut app = App::from_iter_safe(&["hurl", "http://x"]).unwrap();
+        app.oauth_providers
+            .insert("p".to_owned(), provider(url, Grant::ClientCredentials));
+        let mut session = Session::new(&app, "s".to_owned(), "h".to_owned());
+        session.set_oauth_tokens(
+            "p",
+            Tokens {
+                access_token: "old".to_owned(),
+                refresh_token: Some("r1".to_owned()),
+                expires_at: Some(0),
+            },
+        );
+        let mut session = Some(session);
+        let token = access_token(&app, &Client::new(), "p", &mut session, false).unwrap();
+        let request = server.join().unwrap();
+        assert!(request.ends_with("grant_type=refresh_token&refresh_token=r1&client_id=cli"));
+        assert_eq!(token, "a3");
+        let tokens = session.unwrap().oauth_tokens("p").cloned().unwrap();
+        assert_eq!(tokens.access_token, "a3");
+        assert_eq!(tokens.refresh_token.as_deref(), Some("r1"));
+    }
+
+    #[test]
+    fn uses_unexpired_tokens() {
+        let mut app = App::from_iter_safe(&["hurl", "http://x"]).unwrap();
+        app.oauth_providers.insert(
+            "p".to_owned(),
+            provider("http://127.0.0.1:9/token".to_owned(), Grant::ClientCredentials),
+        );
+        let mut session = Session::new(&app, "s".to_owned(), "h".to_owned());
+        session.set_oauth_tokens(
+            "p",
+            Tokens {
+                access_token: "cached".to_owned(),
+                refresh_token: None,
+                expires_at: None,
+            },
+        );
+        let token = access_token(&app, &Client::new(), "p", &mut Some(session), false).unwrap();
+        assert_eq!(token, "cached");
+    }
+
+    #[test]
+    fn receives_the_code_for_the_state() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let browser = thread::spawn(move || {
+            let forged = get(port, "/callback?code=evil&state=other");
+            let real = get(port, "/callback?code=abc&state=xyz");
+            (forged, real)
+        });
+        let code = receive_callback(&listener, "xyz", Duration::from_secs(10)).unwrap();
+        let (forged, real) = browser.join().unwrap();
+        assert_eq!(code, "abc");
+        assert!(forged.starts_with("HTTP/1.1 400"));
+        assert!(real.starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn reports_errors_as_failures() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let browser = thread::spawn(move || get(port, "/callback?error=access_denied&state=xyz"));
+        let result = receive_callback(&listener, "xyz", Duration::from_secs(10));
+        assert!(matches!(result, Err(Error::OAuth(_))));
+        assert!(browser.join().unwrap().starts_with("HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn times_out() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let result = receive_callback(&listener, "xyz", Duration::from_millis(10));
+        assert!(matches!(result, Err(Error::OAuth(_))));
+    }
+}
@@ -0,0 +1,289 @@
+use crate::crypto::hex;
+use crate::errors::{Error, HurlResult};
+use crate::source;
+use crate::upload;
+use rand::RngCore;
+use reqwest::Body;
+use sha2::{Digest, Sha256, Sha512};
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+
+/// A request body which can be hashed and sent more than once
+///
+/// reqwest does not give back the body of a built request, so the body
+/// is kept here and a new `Body` made each time the request is sent.
+#[derive(Debug)]
+pub enum Payload{
+    /// A body built in memory, from parameters or a request file
+    Bytes(Vec<u8>),
+    /// A file given to --upload, streamed each time it is sent
+    File(PathBuf),
+    /// A multipart form, whose file parts are streamed each time it is sent
+    Form(Form),
+    /// Stdin given to --upload, which can only be sent once
+    Stdin,
+}
+
+impl Payload{
+    /// The body for --upload
+    ///
+    /// Stdin is read into memory when `replay` is set, as the body is
+    /// then hashed or may be sent again.
+    pub fn upload(path: &Path, replay: bool) -> HurlResult<Payload>{
+        if path != Path::new("-"){
+            return Ok(Payload::File(path.to_owned()));
+        }
+        if replay{
+            Ok(Payload::Bytes(source::read("-")?))
+        } else {
+            Ok(Payload::Stdin)
+        }
+    }
+
+    /// A new body to send, showing the progress of an upload on stderr
+    /// when `show_progress` is set
+    pub fn body(&self, show_progress: bool) -> HurlResult<Body>{
+        match self{
+            Payload::Bytes(bytes) => Ok(Body::from(bytes.clone())),
+            Payload::File(path) => upload::body(path, show_progress),
+            Payload::Form(form) => {
+                let (reader, len) = form.reader()?;
+                Ok(Body::sized(reader, len))
+            }
+            Payload::Stdin => upload::body(Path::new("-"), show_progress),
+        }
+    }
+
+    /// The whole body, reading files into memory
+    pub fn bytes(&self) -> HurlResult<Cow<'_, [u8]>>{
+        match self{
+            Payload::Bytes(bytes) => Ok(Cow::Borrowed(bytes)),
+            Payload::File(path) => Ok(Cow::Owned(fs::read(path)?)),
+            Payload::Form(form) => {
+                let mut bytes = Vec::new();
+                form.reader()?.0.read_to_end(&mut bytes)?;
+                Ok(Cow::Owned(bytes))
+            }
+            Payload::Stdin => Err(Error::BodyUnavailable),
+        }
+    }
+
+    /// The SHA-256 hash of the body, reading files as it goes
+    pub fn sha256(&self) -> HurlResult<Vec<u8>>{
+        self.digest::<Sha256>()
+    }
+
+    /// The SHA-512 hash of the body, reading files as it goes
+    pub fn sha512(&self) -> HurlResult<Vec<u8>>{
+        self.digest::<Sha512>()
+    }
+
+    fn digest<D: Digest + io::Write>(&self) -> HurlResult<Vec<u8>>{
+        let mut hasher = D::new();
+        match self{
+            Payload::File(path) => {
+                io::copy(&mut File::open(path)?, &mut hasher)?;
+            }
+            Payload::Form(form) => {
+                io::copy(&mut form.reader()?.0, &mut hasher)?;
+            }
+            _ => hasher.update(&self.bytes()?),
+        }
+        Ok(hasher.finalize().to_vec())
+    }
+}
+
+/// A multipart/form-data body
+///
+/// Unlike reqwest's, this can be hashed and sent again. Only the encoding
+/// around the parts is kept in memory, the contents of files are read
+/// each time the form is.
+#[derive(Debug)]
+pub struct Form{
+    boundary: String,
+    parts: Vec<(String, Part)>,
+}
+
+impl Form{
+    pub fn new() -> Form{
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Form{
+            boundary: hex(&bytes),
+            parts: Vec::new(),
+        }
+    }
+
+    pub fn text(self, name: String, value: String) -> Form{
+        self.part(name, Part::text(value))
+    }
+
+    pub fn part(mut self, name: String, part: Part) -> Form{
+        self.parts.push((name, part));
+        self
+    }
+
+    /// The Content-Type header naming the boundary
+    pub fn content_type(&self) -> String{
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// A reader of the encoded form, opening the files of file parts,
+    /// and its length
+    fn reader(&self) -> HurlResult<(Box<dyn Read + Send>, u64)>{
+        let mut reader: Box<dyn Read + Send> = Box::new(io::empty());
+        let mut len = 0;
+        for (name, part) in &self.parts{
+            let mut head = format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+                self.boundary,
+                quote(name)
+            );
+            if let Some(file_name) = &part.file_name{
+                head.push_str(&format!("; filename=\"{}\"", quote(file_name)));
+            }
+            head.push_str("\r\n");
+            if let Some(content_type) = &part.content_type{
+                head.push_str(&format!("Content-Type: {}\r\n", content_type));
+            }
+            head.push_str("\r\n");
+            len += head.len() as u64;
+            reader = Box::new(reader.chain(Cursor::new(head.into_bytes())));
+            match &part.data{
+                Data::Bytes(bytes) => {
+                    len += bytes.len() as u64;
+                    reader = Box::new(reader.chain(Cursor::new(bytes.clone())));
+                }
+                Data::File(path) => {
+                    let file = File::open(path)?;
+                    len += file.metadata()?.len();
+                    reader = Box::new(reader.chain(file));
+                }
+            }
+            len += 2;
+            reader = Box::new(reader.chain(&b"\r\n"[..]));
+        }
+        let tail = format!("--{}--\r\n", self.boundary);
+        len += tail.len() as u64;
+        Ok((Box::new(reader.chain(Cursor::new(tail.into_bytes()))), len))
+    }
+}
+
+/// One field of a multipart form
+#[derive(Debug)]
+pub struct Part{
+    data: Data,
+    content_type: Option<String>,
+    file_name: Option<String>,
+}
+
+impl Part{
+    pub fn bytes(data: Vec<u8>) -> Part{
+        Part{
+            data: Data::Bytes(data),
+            content_type: None,
+            file_name: None,
+        }
+    }
+
+    pub fn text(value: String) -> Part{
+        Part::bytes(value.into_bytes())
+    }
+
+    /// A part with the contents of the file at `path`, named after it and
+    /// with a content type guessed from its extension
+    ///
+    /// The file is only opened here to check that it can be, and is read
+    /// when the form is sent or hashed.
+    pub fn file(path: &Path) -> HurlResult<Part>{
+        File::open(path)?;
+        Ok(Part{
+            data: Data::File(path.to_owned()),
+            content_type: Some(mime_guess::from_path(path).first_or_octet_stream().to_string()),
+            file_name: path.file_name().map(|name| name.to_string_lossy().into_owned()),
+        })
+    }
+
+    pub fn mime_str(mut self, content_type: &str) -> Part{
+        self.content_type = Some(content_type.to_owned());
+        self
+    }
+
+    pub fn file_name(mut self, file_name: String) -> Part{
+        self.file_name = Some(file_name);
+        self
+    }
+}
+
+/// The contents of a part
+#[derive(Debug)]
+enum Data{
+    Bytes(Vec<u8>),
+    File(PathBuf),
+}
+
+/// A name for a quoted Content-Disposition parameter, encoding quotes
+/// and line breaks as browsers do
+fn quote(name: &str) -> String{
+    name.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_multipart_forms() {
+        let form = Form::new()
+            .text("a".to_owned(), "1".to_owned())
+            .part(
+                "f\"".to_owned(),
+                Part::bytes(b"data".to_vec()).mime_str("text/plain").file_name("x.txt".to_owned()),
+            );
+        let boundary = form.boundary.clone();
+        assert_eq!(form.content_type(), format!("multipart/form-data; boundary={}", boundary));
+        let expected = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"f%22\"; filename=\"x.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\ndata\r\n--{b}--\r\n",
+            b = boundary
+        );
+        let payload = Payload::Form(form);
+        assert_eq!(String::from_utf8(payload.bytes().unwrap().into_owned()).unwrap(), expected);
+        assert_eq!(payload.sha256().unwrap(), Sha256::digest(expected.as_bytes()).to_vec());
+    }
+
+    #[test]
+    fn reads_file_parts_when_sent() {
+        let path = std::env::temp_dir().join("hurl-payload-part.txt");
+        fs::write(&path, b"old").unwrap();
+        let form = Form::new().part("f".to_owned(), Part::file(&path).unwrap());
+        fs::write(&path, b"new contents").unwrap();
+        let (mut reader, len) = form.reader().unwrap();
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).unwrap();
+        fs::remove_file(&path).unwrap();
+        let expected = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"f\"; filename=\"hurl-payload-part.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\nnew contents\r\n--{b}--\r\n",
+            b = form.boundary
+        );
+        assert_eq!(String::from_utf8(bytes).unwrap(), expected);
+        assert_eq!(len, expected.len() as u64);
+        assert!(Part::file(&path).is_err());
+    }
+
+    #[test]
+    fn hashes_bytes_and_files() {
+        let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(hex(&Payload::Bytes(Vec::new()).sha256().unwrap()), empty);
+
+        let path = std::env::temp_dir().join("hurl-payload-test");
+        fs::write(&path, b"").unwrap();
+        let hash = Payload::File(path.clone()).sha256().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(hex(&hash), empty);
+    }
+}
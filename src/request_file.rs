@@ -0,0 +1,271 @@
+use crate::app::Parameter;
+use crate::errors::{Error, HurlResult};
+use log::trace;
+use std::fs;
+use std::path::Path;
+
+/// A single request read from a `.http` file
+///
+/// The format is the one used by the VS Code REST client and the
+/// JetBrains HTTP client: a request line, headers, a blank line and
+/// an optional body. Requests are separated by lines starting with `###`.
+#[derive(Debug)]
+pub struct RequestEntry{
+    pub name: Option<String>,
+    pub method: reqwest::Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+}
+
+impl RequestEntry{
+    pub fn display_name(&self) -> String{
+        match &self.name{
+            Some(name) => name.clone(),
+            None => format!("{} {}", self.method, self.url),
+        }
+    }
+
+    pub fn parameters(&self) -> Vec<Parameter>{
+        self.headers
+            .iter()
+            .map(|(key, value)| Parameter::Header{
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect()
+    }
+}
+
+pub fn read_file(path: &Path) -> HurlResult<Vec<RequestEntry>>{
+    let content = fs::read_to_string(path)?;
+    parse(&content)
+}
+
+/// Keep only the entry called `name`, or every entry if no name is given
+pub fn select(entries: Vec<RequestEntry>, name: Option<&str>) -> HurlResult<Vec<RequestEntry>>{
+    match name{
+        None => Ok(entries),
+        Some(name) => {
+            let selected: Vec<RequestEntry> = entries
+                .into_iter()
+                .filter(|e| e.name.as_ref().map(|n| n == name).unwrap_or(false))
+                .collect();
+            if selected.is_empty(){
+                return Err(Error::RequestNotFound(name.to_owned()));
+            }
+            Ok(selected)
+        }
+    }
+}
+
+pub fn parse(content: &str) -> HurlResult<Vec<RequestEntry>>{
+    let mut entries = Vec::new();
+    let mut block: Vec<(usize, &str)> = Vec::new();
+    let mut block_name: Option<String> = None;
+
+    for (i, line) in content.lines().enumerate(){
+        if line.starts_with("###"){
+            if let Some(entry) = parse_block(&block, block_name.take())?{
+                entries.push(entry);
+            }
+            block.clear();
+            let name = line.trim_start_matches('#').trim();
+            if !name.is_empty(){
+                block_name = Some(name.to_owned());
+            }
+            continue;
+        }
+        block.push((i + 1, line));
+    }
+    if let Some(entry) = parse_block(&block, block_name)?{
+        entries.push(entry);
+    }
+    trace!("Parsed {} requests from file", entries.len());
+    Ok(entries)
+}
+
+fn comment(line: &str) -> Option<&str>{
+    if line.starts_with('#'){
+        Some(line.trim_start_matches('#').trim())
+    } else if line.starts_with("//"){
+        Some(line.trim_start_matches('/').trim())
+    } else {
+        None
+    }
+}
+
+fn parse_block(lines: &[(usize, &str)], mut name: Option<String>) -> HurlResult<Option<RequestEntry>>{
+    let mut iter = lines.iter().peekable();
+
+    // Leading blank lines and comments, which may carry an `@name`
+    let mut request_line = None;
+    for (n, line) in iter.by_ref(){
+        let line = line.trim();
+        if line.is_empty(){
+            continue;
+        }
+        if let Some(c) = comment(line){
+            if c.starts_with("@name"){
+                name = Some(c.trim_start_matches("@name").trim().to_owned());
+            }
+            continue;
+        }
+        request_line = Some((*n, line));
+        break;
+    }
+    let (line_no, request_line) = match request_line{
+        Some(r) => r,
+        None => return Ok(None),
+    };
+
+    let mut parts = request_line.split_whitespace();
+    let first = parts.next().unwrap();
+    let (method, mut url) = match parts.next(){
+        Some(url) => {
+            let method = reqwest::Method::from_bytes(first.as_bytes()).map_err(|_| {
+                Error::RequestFileSyntax(line_no, format!("invalid method '{}'", first))
+            })?;
+            (method, url.to_owned())
+        }
+        None => (reqwest::Method::GET, first.to_owned()),
+    };
+
+    // Query strings may be continued on the following lines
+    while let Some((_, line)) = iter.peek(){
+        let line = line.trim();
+        if line.starts_with('?') || line.starts_with('&'){
+            url.push_str(line);
+            iter.next();
+        } else {
+            break;
+        }
+    }
+
+    let mut headers = Vec::new();
+    for (n, line) in iter.by_ref(){
+        if line.trim().is_empty(){
+            break;
+        }
+        if comment(line.trim()).is_some(){
+            continue;
+        }
+        match line.find(':'){
+            Some(idx) => {
+                let (key, value) = line.split_at(idx);
+                headers.push((key.trim().to_owned(), value[1..].trim().to_owned()));
+            }
+            None => {
+                return Err(Error::RequestFileSyntax(
+                    *n,
+                    format!("expected a header 'Name: value', got '{}'", line),
+                ))
+            }
+        }
+    }
+
+    let body = iter.map(|(_, line)| *line).collect::<Vec<&str>>().join("\n");
+    let body = body.trim_end();
+    let body = if body.is_empty(){
+        None
+    } else {
+        Some(body.to_owned())
+    };
+
+    Ok(Some(RequestEntry{
+        name,
+        method,
+        url,
+        headers,
+        body,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separates_and_names_requests() {
+        let content = "\
+# a comment before the first request
+GET http://a/one
+
+### first
+POST http://a/two
+
+###
+# @name second
+// another comment
+http://a/three
+
+### only comments
+# nothing here
+";
+        let entries = parse(content).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].name, None);
+        assert_eq!(entries[0].display_name(), "GET http://a/one");
+        assert_eq!(entries[1].name.as_deref(), Some("first"));
+        assert_eq!(entries[1].method, reqwest::Method::POST);
+        assert_eq!(entries[2].name.as_deref(), Some("second"));
+        assert_eq!(entries[2].method, reqwest::Method::GET);
+        assert_eq!(entries[2].url, "http://a/three");
+
+        let selected = select(entries, Some("second")).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].url, "http://a/three");
+        assert!(matches!(
+            select(parse(content).unwrap(), Some("third")),
+            Err(Error::RequestNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn continues_query_strings() {
+        let content = "GET http://a/search\n    ?q=rust\n    &page=2\nAccept: text/plain\n";
+        let entries = parse(content).unwrap();
+        assert_eq!(entries[0].url, "http://a/search?q=rust&page=2");
+        assert_eq!(entries[0].headers, vec![("Accept".to_owned(), "text/plain".to_owned())]);
+        assert!(entries[0].body.is_none());
+    }
+
+    #[test]
+    fn reads_headers_and_bodies() {
+        let content = "\
+PUT http://a/item
+Content-Type: application/json
+# a comment among the headers
+X-Time: 12:30
+
+{
+  \"a\": 1,
+
+  \"b\": 2
+}
+
+
+### next
+GET http://a/next
+";
+        let entries = parse(content).unwrap();
+        assert_eq!(
+            entries[0].headers,
+            vec![
+                ("Content-Type".to_owned(), "application/json".to_owned()),
+                ("X-Time".to_owned(), "12:30".to_owned()),
+            ]
+        );
+        assert_eq!(entries[0].body.as_deref(), Some("{\n  \"a\": 1,\n\n  \"b\": 2\n}"));
+        assert!(entries[1].body.is_none());
+        assert_eq!(entries[0].parameters().len(), 2);
+    }
+
+    #[test]
+    fn reports_the_line_of_syntax_errors() {
+        let result = parse("### x\n\nFETCH( http://a\n");
+        assert!(matches!(result, Err(Error::RequestFileSyntax(3, _))));
+        let result = parse("GET http://a\nAccept: */*\nnot a header\n");
+        assert!(matches!(result, Err(Error::RequestFileSyntax(3, _))));
+    }
+}
@@ -1,15 +1,22 @@
-use crate::app::{App, Parameter};
+use crate::app::{App, Parameter, SessionCommand};
+use crate::client;
+use crate::credentials;
+use crate::digest;
 use crate::directories::DIRECTORIES;
-use crate::errors::HurlResult;
+use crate::encryption;
+use crate::oauth;
+use crate::time;
+use crate::errors::{Error, HurlResult};
 use reqwest::header::COOKIE;
 use reqwest::RequestBuilder;
 use serde::{Deserialize,Serialize};
 use std::collections::HashMap;
-use std::fs::{create_dir_all, File, OpenOptions};
-use std::io::{BufReader, BufWriter};
-use std::path::PathBuf;
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Session{
     path: PathBuf,
     name: String,
@@ -18,6 +25,21 @@ pub struct Session{
     token: Option<String>,
     headers: HashMap<String, String>,
     cookies: Vec<(String, String)>,
+    #[serde(default)]
+    variables: HashMap<String, String>,
+    /// The login of credentials read from .netrc, which are looked up
+    /// again rather than stored
+    #[serde(default)]
+    netrc_login: Option<String>,
+    /// The last Digest challenge answered, by host
+    #[serde(default)]
+    digest: HashMap<String, digest::Challenge>,
+    /// OAuth2 tokens, by provider name
+    #[serde(default)]
+    oauth: HashMap<String, oauth::Tokens>,
+    /// Expiry of cookies as seconds since the Unix epoch, by cookie name
+    #[serde(default)]
+    cookie_expires: HashMap<String, u64>,
 }
 
 impl Session{
@@ -30,63 +52,139 @@ impl Session{
             ..Default::default()
         }
     }
-}
+
+    pub fn variables(&self) -> &HashMap<String, String>{
+        &self.variables
+    }
+
+    pub fn set_variable(&mut self, name: String, value: String){
+        self.variables.insert(name, value);
+    }
+
+    pub fn update_netrc(&mut self, login: &str){
+        self.netrc_login = Some(login.to_owned());
+    }
+
+    pub fn digest_challenge(&self, host: &str) -> Option<&digest::Challenge>{
+        self.digest.get(host)
+    }
+
+    pub fn set_digest_challenge(&mut self, host: &str, challenge: digest::Challenge){
+        self.digest.insert(host.to_owned(), challenge);
+    }
+
+    pub fn oauth_tokens(&self, provider: &str) -> Option<&oauth::Tokens>{
+        self.oauth.get(provider)
+    }
+
+    pub fn set_oauth_tokens(&mut self, provider: &str, tokens: oauth::Tokens){
+        self.oauth.insert(provider.to_owned(), tokens);
+    }
+
+    pub fn has_header(&self, name: &str) -> bool{
+        self.headers.keys().any(|k|k.eq_ignore_ascii_case(name))
+    }
+
+    /// A copy with the passwords, tokens, cookies, variables and
+    /// sensitive headers masked
+    ///
+    /// `keyring:` references are kept, as they name a secret without
+    /// giving it away.
+    fn masked(&self) -> Session{
+        let mut session = self.clone();
+        session.auth = self.auth.as_ref().map(|auth|match auth.find(':'){
+            Some(idx) if !credentials::is_keyring_ref(auth) => format!("{}:{}", &auth[..idx], MASK),
+            _ => auth.clone(),
+        });
+        session.token = self.token.as_ref().map(|token|{
+            if credentials::is_keyring_ref(token){
+                token.clone()
+            } else {
+                MASK.to_owned()
+            }
+        });
+        for tokens in session.oauth.values_mut(){
+            tokens.access_token = MASK.to_owned();
+            tokens.refresh_token = tokens.refresh_token.as_ref().map(|_|MASK.to_owned());
+        }
+        for (key, value) in session.headers.iter_mut(){
+            if is_sensitive_header(key){
+                *value = MASK.to_owned();
+            }
+        }
+        for (_, value) in session.cookies.iter_mut(){
+            *value = MASK.to_owned();
+        }
+        for value in session.variables.values_mut(){
+            *value = MASK.to_owned();
+        }
+        session
+    }
 pub fn load(app: &App, name: &str, host: &str) -> HurlResult<Self>{
     let path = Session::path(app, name, host );
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    serde_json::from_reader(reader).map_err(|e|e.into())
+    read_session(app, &path)
 }
-pub fn get_or_crate(app: &App, name: String, host: String) -> Self{
-    match Session::load(app, &name, &host){
-        Ok(session) => session,
-        Err(_) => Session::new(app, name, host ),
+/// Load a session, or start a new one if none is stored yet
+///
+/// A stored session which cannot be read is an error rather than being
+/// silently replaced, as it may just need the right session key.
+pub fn get_or_create(app: &App, name: String, host: String) -> HurlResult<Self>{
+    if !Session::path(app, &name, &host).is_file(){
+        return Ok(Session::new(app, name, host ));
     }
+    Session::load(app, &name, &host)
 }
 
 
 fn path(app: &App, name: &str, host: &str) -> PathBuf{
     let mut session_dir = Session::dir(app, host);
     let mut filename = make_safe_pathname(name);
-    filename.puth_str(".json");
+    filename.push_str(".json");
     session_dir.push(filename);
     session_dir
 }
 
-fn dir(app: &App, host: &str) -> PuthBuf{
-    let mut session_dir = app
+fn dir(app: &App, host: &str) -> PathBuf{
+    let mut session_dir = Session::root(app);
+    session_dir.push(make_safe_pathname(host));
+    session_dir
+}
+
+fn root(app: &App) -> PathBuf{
+    app
         .session_dir
         .as_ref()
         .cloned()
         .filter(|session_dir|session_dir.is_dir())
-        .unwrap_or_else(|| DERICTORIES.config().join("sessions"));
-    session_dir.push(make_safe_pathname(host));
-    session_dir
+        .unwrap_or_else(|| DIRECTORIES.config().join("sessions"))
 }
 
+/// Write the session to disk, encrypted if a session key is set
 pub fn save(&self, app: &App) -> HurlResult<()>{
     let dir = Session::dir(app, &self.host);
     create_dir_all(dir)?;
-    let file = OpenOption::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(&self.path)?;
-    let write = BufWrite::new(file);
-    serde_json::to_writer(writer, &self).map_err(|e|e.into())
+    let json = serde_json::to_vec(&self)?;
+    match &app.session_key{
+        Some(key) => write_atomic(&self.path, &encryption::encrypt(key, &json)?),
+        None => write_atomic(&self.path, &json),
+    }
 }
 
-pub fn update_with_parameters(&mut self, parameters: &Vec<Parameter>){
+/// Store the headers given with the request
+///
+/// `Header:` and `Header;` only apply to the one request, so unset and
+/// empty headers are not stored and leave a stored header as it is.
+pub fn update_with_parameters(&mut self, parameters: &[Parameter]){
     for parameter in parameters.iter(){
-        match parameter {
-            Parameter::Header{key, value} => {
-                let lower_key = key.to_ascii_lowercase();
-                if lower_key.starts_with("content-")||lower_key.starts_with("if-"){
-                    continue;
-                }
-                self.headers.insert(key.clone(), value.clone());
+        if let Parameter::Header{key, value} = parameter {
+            let lower_key = key.to_ascii_lowercase();
+            if lower_key.starts_with("content-")||lower_key.starts_with("if-"){
+                continue;
             }
-            _ => {}
+            if value.is_empty(){
+                continue;
+            }
+            self.headers.insert(key.clone(), value.clone());
         }
     }
 }
@@ -101,8 +199,15 @@ pub fn update_auth(&mut self , auth: &Option<String>, token:&Option<String>){
 }
 
 
-pub fn add_to_request(&self, mut builder: RequestBuilder) -> RequestBuilder{
+/// Add the stored headers and cookies to a request
+///
+/// Stored headers which are also given as parameters are left out so that
+/// the command line takes precedence.
+pub fn add_to_request(&self, mut builder: RequestBuilder, parameters: &[Parameter]) -> RequestBuilder{
     for (key, value) in self.headers.iter(){
+        if parameters.iter().any(|p|p.is_header(key)){
+            continue;
+        }
         builder = builder.header(key, value);
     }
     let cookies = self
@@ -111,18 +216,44 @@ pub fn add_to_request(&self, mut builder: RequestBuilder) -> RequestBuilder{
         .map(|(name,value)|format!("{} = {}", name, value))
         .collect::<Vec<String>>()
         .join("; ");
-    if cookies.is_empty(){
+    if cookies.is_empty() || parameters.iter().any(|p|p.is_header("cookie")){
         return builder;
     }
     builder.header(COOKIE, cookies)
 }
 
-pub fn update_with_response(&mut self, resp: &reqwest::Respone){
+pub fn update_with_response(&mut self, resp: &reqwest::Response){
     for cookie in resp.cookies(){
-        self.cookies
-            .push((cookie.name().to_owned(), cookie.value().to_owned()));
+        let name = cookie.name().to_owned();
+        // A cookie set again replaces the stored one, as in a browser.
+        // Keeping both would send the stale value too, and the expiry
+        // is stored by name.
+        self.cookies.retain(|(n, _)|*n != name);
+        self.cookies.push((name.clone(), cookie.value().to_owned()));
+        let expires = cookie
+            .expires()
+            .or_else(|| cookie.max_age().and_then(|age|SystemTime::now().checked_add(age)))
+            .and_then(|t|t.duration_since(UNIX_EPOCH).ok());
+        match expires{
+            Some(e) => self.cookie_expires.insert(name, e.as_secs()),
+            None => self.cookie_expires.remove(&name),
+        };
     }
 }
+}
+
+/// The name sessions for `url` are stored under: its scheme, host and
+/// port, so that every path on a server shares the session
+///
+/// A URL which cannot be parsed yet, such as one with a variable in
+/// its host, is used whole.
+pub fn host_key(app: &App, url: &str) -> String{
+    match client::parse(app, url){
+        Ok(url) => make_safe_pathname(&url.origin().ascii_serialization()),
+        Err(_) => make_safe_pathname(url),
+    }
+}
+
 pub fn make_safe_pathname(s:&str) -> String{
     let mut buf = String::with_capacity(s.len());
     for c in s.chars(){
@@ -132,4 +263,431 @@ pub fn make_safe_pathname(s:&str) -> String{
         }
     }
     buf
-}
\ No newline at end of file
+}
+pub fn run_command(app: &App, cmd: &SessionCommand) -> HurlResult<()>{
+    match cmd{
+        SessionCommand::List => list(app),
+        SessionCommand::Show{host, name, reveal} => {
+            let session = load_existing(app, name, host)?;
+            show(&session, *reveal);
+            Ok(())
+        }
+        SessionCommand::Delete{host, name} => {
+            let session = load_existing(app, name, host)?;
+            fs::remove_file(&session.path)?;
+            println!("Deleted session {} for {}", name, host);
+            Ok(())
+        }
+        SessionCommand::Rename{host, name, new_name} => {
+            let mut session = load_existing(app, name, host)?;
+            let old_path = session.path.clone();
+            let new_path = Session::path(app, new_name, &session.host);
+            if new_path.exists(){
+                return Err(Error::SessionExists(new_name.clone()));
+            }
+            session.name = new_name.clone();
+            session.path = new_path;
+            session.save(app)?;
+            fs::remove_file(old_path)?;
+            println!("Renamed session {} to {}", name, new_name);
+            Ok(())
+        }
+        SessionCommand::ClearCookies{host, name} => {
+            let mut session = load_existing(app, name, host)?;
+            session.cookies.clear();
+            session.cookie_expires.clear();
+            session.save(app)?;
+            println!("Cleared cookies of session {}", name);
+            Ok(())
+        }
+        SessionCommand::Export{host, name, file, reveal} => {
+            let session = load_existing(app, name, host)?;
+            let session = if *reveal { session } else { session.masked() };
+            match file{
+                Some(file) => {
+                    let writer = BufWriter::new(File::create(file)?);
+                    serde_json::to_writer_pretty(writer, &session)?;
+                }
+                None => {
+                    serde_json::to_writer_pretty(io::stdout(), &session)?;
+                    println!();
+                }
+            }
+            Ok(())
+        }
+        SessionCommand::Encrypt{host, name} => {
+            let key = app.session_key.as_ref().ok_or(Error::SessionKeyRequired)?;
+            let mut failed = 0;
+            for (host_dir, path) in session_files(app)?{
+                if host.as_ref().map(|h|host_key(app, h) != host_dir).unwrap_or(false){
+                    continue;
+                }
+                match encrypt_file(key, &path, name.as_deref()){
+                    Ok(Some(session)) => println!("Encrypted session {} for {}", session, host_dir),
+                    Ok(None) => {}
+                    Err(e) => {
+                        eprintln!("Cannot encrypt {}: {}", path.display(), e);
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0{
+                return Err(Error::SessionsNotEncrypted(failed));
+            }
+            Ok(())
+        }
+        SessionCommand::Import{file, host, name, force} => {
+            let reader = BufReader::new(File::open(file)?);
+            let mut session: Session = serde_json::from_reader(reader)?;
+            if let Some(host) = host{
+                session.host = host_key(app, host);
+            }
+            if let Some(name) = name{
+                session.name = name.clone();
+            }
+            session.path = Session::path(app, &session.name, &session.host);
+            if session.path.exists() && !force{
+                return Err(Error::SessionExists(session.name));
+            }
+            session.save(app)?;
+            println!("Imported session {} for {}", session.name, session.host);
+            Ok(())
+        }
+    }
+}
+
+/// Encrypt a session file unless it is already encrypted or is not the
+/// session `name`, giving the name of the session encrypted
+fn encrypt_file(key: &str, path: &Path, name: Option<&str>) -> HurlResult<Option<String>>{
+    let content = fs::read(path)?;
+    if encryption::is_encrypted(&content){
+        return Ok(None);
+    }
+    let session: Session = serde_json::from_slice(&content)?;
+    if name.map(|n|n != session.name).unwrap_or(false){
+        return Ok(None);
+    }
+    write_atomic(path, &encryption::encrypt(key, &content)?)?;
+    Ok(Some(session.name))
+}
+
+/// Replace the file at `path` with `content`
+///
+/// The content is written to a temporary file which is then renamed, so
+/// a failure leaves the old file as it was.
+fn write_atomic(path: &Path, content: &[u8]) -> HurlResult<()>{
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let result = File::create(&tmp)
+        .and_then(|file|{
+            let mut writer = BufWriter::new(file);
+            writer.write_all(content)?;
+            writer.into_inner().map_err(|e|e.into_error())?.sync_all()
+        })
+        .and_then(|_|fs::rename(&tmp, path));
+    if result.is_err(){
+        let _ = fs::remove_file(&tmp);
+    }
+    result.map_err(From::from)
+}
+
+/// Load a session which must already exist
+///
+/// `host` may be given either as the URL used with the session or as the
+/// host directory name shown by `hurl session list`.
+fn load_existing(app: &App, name: &str, host: &str) -> HurlResult<Session>{
+    let host = host_key(app, host);
+    if !Session::path(app, name, &host).is_file(){
+        return Err(Error::SessionNotFound(name.to_owned(), host));
+    }
+    Session::load(app, name, &host)
+}
+
+/// Every stored session file along with the name of its host directory
+fn session_files(app: &App) -> HurlResult<Vec<(String, PathBuf)>>{
+    let root = Session::root(app);
+    let mut files = Vec::new();
+    let hosts = match fs::read_dir(&root){
+        Ok(hosts) => hosts,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(files),
+        Err(e) => return Err(e.into()),
+    };
+    for host in hosts{
+        let host = host?;
+        if !host.file_type()?.is_dir(){
+            continue;
+        }
+        for file in fs::read_dir(host.path())?{
+            let path = file?.path();
+            if path.extension().map(|e|e != "json").unwrap_or(true){
+                continue;
+            }
+            files.push((host.file_name().to_string_lossy().into_owned(), path));
+        }
+    }
+    Ok(files)
+}
+
+fn list(app: &App) -> HurlResult<()>{
+    let mut rows = Vec::new();
+    for (host, path) in session_files(app)?{
+        let row = match read_session(app, &path){
+            Ok(session) => vec![
+                host,
+                session.name.clone(),
+                session.headers.len().to_string(),
+                session.cookies.len().to_string(),
+            ],
+            Err(e) => vec![
+                host,
+                path.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+                format!("unreadable: {}", e),
+                String::new(),
+            ],
+        };
+        rows.push(row);
+    }
+    rows.sort();
+    print_table(&["HOST", "NAME", "HEADERS", "COOKIES"], &rows);
+    Ok(())
+}
+
+/// Read a session file, decrypting it if it is encrypted
+fn read_session(app: &App, path: &Path) -> HurlResult<Session>{
+    let content = fs::read(path)?;
+    if encryption::is_encrypted(&content){
+        let key = app.session_key.as_ref().ok_or(Error::SessionKeyRequired)?;
+        let json = encryption::decrypt(key, &content)?;
+        return serde_json::from_slice(&json).map_err(|_|Error::CorruptEncryptedFile);
+    }
+    serde_json::from_slice(&content).map_err(|e|e.into())
+}
+
+fn show(session: &Session, reveal: bool){
+    let session = if reveal { session.clone() } else { session.masked() };
+
+    println!("Session: {}", session.name);
+    println!("Host:    {}", session.host);
+    println!("File:    {}", session.path.display());
+    if let Some(auth) = &session.auth{
+        println!("Auth:    {}", auth);
+    }
+    if let Some(login) = &session.netrc_login{
+        println!("Auth:    {} (from .netrc)", login);
+    }
+    if let Some(token) = &session.token{
+        println!("Token:   {}", token);
+    }
+    let mut providers: Vec<&String> = session.oauth.keys().collect();
+    providers.sort();
+    for provider in providers{
+        let tokens = &session.oauth[provider];
+        let expires = match tokens.expires_at{
+            Some(secs) => format_time(secs),
+            None => "never".to_owned(),
+        };
+        println!("OAuth:   {} {} (expires {})", provider, tokens.access_token, expires);
+    }
+
+    let mut headers: Vec<Vec<String>> = session
+        .headers
+        .iter()
+        .map(|(key, value)|vec![key.clone(), value.clone()])
+        .collect();
+    headers.sort();
+    if !headers.is_empty(){
+        println!();
+        print_table(&["HEADER", "VALUE"], &headers);
+    }
+
+    let cookies: Vec<Vec<String>> = session
+        .cookies
+        .iter()
+        .map(|(name, value)|{
+            let expires = match session.cookie_expires.get(name){
+                Some(secs) => format_time(*secs),
+                None => "session".to_owned(),
+            };
+            vec![name.clone(), value.clone(), expires]
+        })
+        .collect();
+    if !cookies.is_empty(){
+        println!();
+        print_table(&["COOKIE", "VALUE", "EXPIRES"], &cookies);
+    }
+
+    let mut variables: Vec<Vec<String>> = session
+        .variables
+        .iter()
+        .map(|(name, value)|vec![name.clone(), value.clone()])
+        .collect();
+    variables.sort();
+    if !variables.is_empty(){
+        println!();
+        print_table(&["VARIABLE", "VALUE"], &variables);
+    }
+}
+
+const MASK: &str = "********";
+
+fn is_sensitive_header(key: &str) -> bool{
+    let key = key.to_ascii_lowercase();
+    ["auth", "token", "key", "secret", "cookie", "signature"]
+        .iter()
+        .any(|s|key.contains(s))
+}
+
+fn format_time(secs: u64) -> String{
+    let expired = secs < time::now();
+    let time = time::format_utc(secs);
+    if expired{
+        format!("{} (expired)", time)
+    } else {
+        time
+    }
+}
+
+fn print_table(header: &[&str], rows: &[Vec<String>]){
+    let mut widths: Vec<usize> = header.iter().map(|h|h.len()).collect();
+    for row in rows.iter(){
+        for (i, cell) in row.iter().enumerate(){
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<&str>|{
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(c, w)|format!("{:w$}", c, w = *w))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(header.to_vec());
+    for row in rows.iter(){
+        line(row.iter().map(|c|c.as_str()).collect());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    #[test]
+    fn masks_secrets() {
+        let mut session = Session{
+            auth: Some("user:pass".to_owned()),
+            token: Some("token".to_owned()),
+            ..Session::default()
+        };
+        session.headers.insert("Authorization".to_owned(), "Bearer x".to_owned());
+        session.headers.insert("Accept".to_owned(), "text/plain".to_owned());
+        session.cookies.push(("id".to_owned(), "secret".to_owned()));
+        session.variables.insert("token".to_owned(), "secret".to_owned());
+
+        let masked = session.masked();
+        assert_eq!(masked.auth.as_deref(), Some("user:********"));
+        assert_eq!(masked.token.as_deref(), Some(MASK));
+        assert_eq!(masked.headers["Authorization"], MASK);
+        assert_eq!(masked.headers["Accept"], "text/plain");
+        assert_eq!(masked.cookies[0], ("id".to_owned(), MASK.to_owned()));
+        assert_eq!(masked.variables["token"], MASK);
+
+        let session = Session{
+            auth: Some("keyring:api/ann".to_owned()),
+            token: Some("keyring:api/token".to_owned()),
+            ..Session::default()
+        };
+        let masked = session.masked();
+        assert_eq!(masked.auth.as_deref(), Some("keyring:api/ann"));
+        assert_eq!(masked.token.as_deref(), Some("keyring:api/token"));
+    }
+
+    #[test]
+    fn imports_over_a_session_only_when_forced() {
+        let dir = std::env::temp_dir().join("hurl-session-import-test");
+        fs::create_dir_all(&dir).unwrap();
+        let app = App::from_iter_safe(&["hurl", "--session-dir", dir.to_str().unwrap(), "http://h"]).unwrap();
+        let file = dir.join("export.json");
+        let mut exported = Session::new(&app, "s".to_owned(), host_key(&app, "http://h"));
+        exported.set_variable("v".to_owned(), "new".to_owned());
+        fs::write(&file, serde_json::to_vec(&exported).unwrap()).unwrap();
+        let import = |name: &str, force: bool| {
+            run_command(&app, &SessionCommand::Import{
+                file: file.clone(),
+                host: None,
+                name: Some(name.to_owned()),
+                force,
+            })
+        };
+
+        let mut stored = Session::new(&app, "s".to_owned(), host_key(&app, "http://h"));
+        stored.set_variable("v".to_owned(), "old".to_owned());
+        stored.save(&app).unwrap();
+        let refused = import("s", false);
+        let kept = load_existing(&app, "s", "http://h").unwrap().variables["v"].clone();
+        import("s", true).unwrap();
+        let replaced = load_existing(&app, "s", "http://h").unwrap().variables["v"].clone();
+        import("t", false).unwrap();
+        let imported = load_existing(&app, "t", "http://h").unwrap().variables["v"].clone();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(refused, Err(Error::SessionExists(ref name)) if name == "s"));
+        assert_eq!(kept, "old");
+        assert_eq!(replaced, "new");
+        assert_eq!(imported, "new");
+    }
+
+    #[test]
+    fn keeps_stored_headers_for_unset_and_empty_headers() {
+        let mut session = Session::default();
+        session.update_with_parameters(&[Parameter::Header{key: "X-A".to_owned(), value: "1".to_owned()}]);
+        session.update_with_parameters(&[
+            Parameter::UnsetHeader{key: "X-A".to_owned()},
+            Parameter::Header{key: "X-A".to_owned(), value: String::new()},
+            Parameter::Header{key: "X-B".to_owned(), value: String::new()},
+        ]);
+        assert_eq!(session.headers.len(), 1);
+        assert_eq!(session.headers["X-A"], "1");
+    }
+
+    #[test]
+    fn replaces_files_atomically() {
+        let path = std::env::temp_dir().join("hurl-write-atomic-test.json");
+        fs::write(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        assert!(!PathBuf::from(tmp).exists());
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn shares_a_session_between_paths_on_a_server() {
+        let dir = std::env::temp_dir().join("hurl-session-host-test");
+        fs::create_dir_all(&dir).unwrap();
+        let args = ["hurl", "--session-dir", dir.to_str().unwrap(), "http://h:8080/login"];
+        let app = App::from_iter_safe(&args).unwrap();
+        let login = host_key(&app, "http://h:8080/login");
+        assert_eq!(login, host_key(&app, "http://h:8080/c?m={{m}}"));
+        assert_eq!(host_key(&app, "http://h/login"), host_key(&app, "h/c"));
+        assert_ne!(login, host_key(&app, "https://h:8080/login"));
+        assert_ne!(login, host_key(&app, "http://h:8081/login"));
+
+        let mut session = Session::get_or_create(&app, "s".to_owned(), login).unwrap();
+        session.set_variable("m".to_owned(), "1".to_owned());
+        session.save(&app).unwrap();
+        let next = host_key(&app, "http://h:8080/c?m=1");
+        let session = Session::get_or_create(&app, "s".to_owned(), next).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(session.variables["m"], "1");
+    }
+
+    #[test]
+    fn formats_expired_times() {
+        assert!(format_time(0).contains("expired"));
+        assert!(!format_time(u64::MAX).contains("expired"));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::credentials;
+use crate::crypto::{hex, hmac_sha256, hmac_sha512, insert};
+use crate::errors::{Error, HurlResult};
+use crate::payload::Payload;
+use crate::variables;
+use log::trace;
+use rand::RngCore;
+use reqwest::Request;
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashMap;
+
+/// A request signing scheme, configured as `[signing.NAME]`
+///
+/// The template is filled in for each request and signed with the secret,
+/// and the encoded signature is sent in `header`. The template may use
+/// {method}, {path}, {query}, {host}, {timestamp}, {nonce},
+/// {body_sha256}, {body_sha512} and {header:Name}, and {{ stands for a
+/// literal {.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scheme{
+    pub algorithm: Algorithm,
+    /// May be a `keyring:service/user` reference or use ${VAR}
+    pub secret: String,
+    pub template: String,
+    #[serde(default)]
+    pub encoding: Encoding,
+    pub header: String,
+    /// Prepended to the signature in the header, e.g. "HMAC "
+    #[serde(default)]
+    pub prefix: String,
+    /// A header to send the timestamp used in the signature in
+    pub timestamp_header: Option<String>,
+    #[serde(default)]
+    pub timestamp_format: TimestampFormat,
+    /// A header to send the nonce used in the signature in
+    pub nonce_header: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Algorithm{
+    HmacSha256,
+    HmacSha512,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding{
+    #[default]
+    Hex,
+    Base64,
+    Base64url,
+}
+
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampFormat{
+    /// Seconds since the Unix epoch
+    #[default]
+    Unix,
+    /// Milliseconds since the Unix epoch
+    Millis,
+    Rfc3339,
+}
+
+
+/// Sign a complete request with the body `payload`, adding the signature
+/// header
+pub fn sign(request: &mut Request, scheme: &Scheme, payload: Option<&Payload>) -> HurlResult<()>{
+    let now = chrono::Utc::now();
+    let timestamp = match scheme.timestamp_format{
+        TimestampFormat::Unix => now.timestamp().to_string(),
+        TimestampFormat::Millis => now.timestamp_millis().to_string(),
+        TimestampFormat::Rfc3339 => now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+    };
+    let mut nonce_bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce_bytes);
+    let nonce = hex(&nonce_bytes);
+
+    if let Some(name) = &scheme.timestamp_header{
+        insert(request, name, &timestamp)?;
+    }
+    if let Some(name) = &scheme.nonce_header{
+        insert(request, name, &nonce)?;
+    }
+
+    let canonical = fill_template(&scheme.template, request, payload, &timestamp, &nonce)?;
+    trace!("Signing canonical string:\n{}", canonical);
+
+    let secret = variables::interpolate(&scheme.secret, &HashMap::new(), true)?;
+    let secret = credentials::resolve_token(&secret)?;
+    let signature = match scheme.algorithm{
+        Algorithm::HmacSha256 => hmac_sha256(secret.as_bytes(), canonical.as_bytes()),
+        Algorithm::HmacSha512 => hmac_sha512(secret.as_bytes(), canonical.as_bytes()),
+    };
+    let signature = match scheme.encoding{
+        Encoding::Hex => hex(&signature),
+        Encoding::Base64 => base64::encode(&signature),
+        Encoding::Base64url => base64::encode_config(&signature, base64::URL_SAFE_NO_PAD),
+    };
+    insert(request, &scheme.header, &format!("{}{}", scheme.prefix, signature))
+}
+
+fn fill_template(
+    template: &str,
+    request: &Request,
+    payload: Option<&Payload>,
+    timestamp: &str,
+    nonce: &str,
+) -> HurlResult<String>{
+    let url = request.url();
+
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{'){
+        if rest[start + 1..].starts_with('{'){
+            result.push_str(&rest[..=start]);
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let end = match rest[start..].find('}'){
+            Some(end) => start + end,
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        let placeholder = &rest[start + 1..end];
+        let value = match placeholder{
+            "method" => request.method().as_str().to_owned(),
+            "path" => url.path().to_owned(),
+            "query" => url.query().unwrap_or("").to_owned(),
+            "host" => url.host_str().unwrap_or("").to_owned(),
+            "timestamp" => timestamp.to_owned(),
+            "nonce" => nonce.to_owned(),
+            "body_sha256" => match payload{
+                Some(payload) => hex(&payload.sha256()?),
+                None => hex(&Sha256::digest(b"")),
+            },
+            "body_sha512" => match payload{
+                Some(payload) => hex(&payload.sha512()?),
+                None => hex(&Sha512::digest(b"")),
+            },
+            p if p.starts_with("header:") => request
+                .headers()
+                .get(&p["header:".len()..])
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("")
+                .to_owned(),
+            p => return Err(Error::UnknownSigningPlaceholder(p.to_owned())),
+        };
+        result.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> Request {
+        let mut request = Request::new(reqwest::Method::POST, "http://example.com/a?b=1".parse().unwrap());
+        insert(&mut request, "x-id", "7").unwrap();
+        request
+    }
+
+    #[test]
+    fn fills_templates() {
+        let payload = Payload::Bytes(b"".to_vec());
+        let filled = fill_template(
+            "{method} {host}{path}?{query} {timestamp} {nonce} {header:X-Id} {body_sha256}",
+            &request(),
+            Some(&payload),
+            "1",
+            "n",
+        )
+        .unwrap();
+        assert_eq!(
+            filled,
+            "POST example.com/a?b=1 1 n 7 e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn escapes_braces() {
+        let filled = fill_template("{{\"m\":\"{method}\"}", &request(), None, "1", "n").unwrap();
+        assert_eq!(filled, "{\"m\":\"POST\"}");
+        assert!(matches!(
+            fill_template("{date}", &request(), None, "1", "n"),
+            Err(Error::UnknownSigningPlaceholder(_))
+        ));
+    }
+}
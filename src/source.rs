@@ -0,0 +1,68 @@
+use crate::errors::{Error, HurlResult};
+use lazy_static::lazy_static;
+use log::trace;
+use std::fs;
+use std::io::{self, Read};
+use std::process::Command;
+use std::sync::Mutex;
+
+lazy_static!{
+    /// What stdin held, as it can only be read once
+    static ref STDIN: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+}
+
+/// The contents of the file `path`, or of stdin for `-`
+pub fn read(path: &str) -> io::Result<Vec<u8>>{
+    if path != "-"{
+        trace!("Reading {}", path);
+        return fs::read(path);
+    }
+    let mut stdin = STDIN.lock().unwrap();
+    if stdin.is_none(){
+        trace!("Reading stdin");
+        let mut buf = Vec::new();
+        io::stdin().read_to_end(&mut buf)?;
+        *stdin = Some(buf);
+    }
+    Ok(stdin.clone().unwrap_or_default())
+}
+
+/// The value of a `key:@path` parameter, the file as text with trailing
+/// newlines trimmed
+pub fn file_value(path: &str) -> HurlResult<String>{
+    let invalid = |msg: String| Error::ValueSource(format!("@{}", path), msg);
+    let bytes = read(path).map_err(|e| invalid(e.to_string()))?;
+    let text = String::from_utf8(bytes).map_err(|_| invalid("not UTF-8 text".to_owned()))?;
+    Ok(trim_newlines(text))
+}
+
+/// The value of a `key:<command` parameter, the output of the command
+/// with trailing newlines trimmed
+///
+/// The command is run directly and not through a shell.
+pub fn command_value(cmd: &str) -> HurlResult<String>{
+    Ok(trim_newlines(run_command(cmd)?))
+}
+
+fn trim_newlines(text: String) -> String{
+    text.trim_end_matches(['\n', '\r']).to_owned()
+}
+
+fn run_command(cmd: &str) -> HurlResult<String>{
+    let invalid = |msg: String| Error::ValueSource(format!("<{}", cmd), msg);
+    let args = shell_words::split(cmd).map_err(|e| invalid(e.to_string()))?;
+    let (program, args) = match args.split_first(){
+        Some(split) => split,
+        None => return Err(invalid("no command given".to_owned())),
+    };
+    trace!("Running {} for a parameter value", program);
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| invalid(e.to_string()))?;
+    if !output.status.success(){
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(invalid(format!("{}: {}", output.status, stderr.trim())));
+    }
+    String::from_utf8(output.stdout).map_err(|_| invalid("the output is not UTF-8 text".to_owned()))
+}
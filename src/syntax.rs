@@ -0,0 +1,15 @@
+use crate::errors::{Error, HurlResult};
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+
+/// The syntaxes and themes used to highlight responses
+///
+/// Syntaxes which are not in the set, such as HTTP, are printed as
+/// plain text.
+pub fn build() -> HurlResult<(SyntaxSet, ThemeSet)>{
+    let ss = SyntaxSet::load_defaults_newlines();
+    if ss.find_syntax_by_name("JSON").is_none(){
+        return Err(Error::SyntaxLoadError("JSON"));
+    }
+    Ok((ss, ThemeSet::load_defaults()))
+}
@@ -0,0 +1,40 @@
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The current time as seconds since the Unix epoch
+pub fn now() -> u64{
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Seconds since the Unix epoch as a UTC date
+///
+/// Times too far off to be a date, as may be found in a token or a
+/// cookie, are given as the number of seconds.
+pub fn format_utc(secs: u64) -> String{
+    i64::try_from(secs)
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| secs.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_dates() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_utc(1_600_000_000), "2020-09-13 12:26:40 UTC");
+    }
+
+    #[test]
+    fn gives_out_of_range_times_as_numbers() {
+        assert_eq!(format_utc(i64::MAX as u64), i64::MAX.to_string());
+        assert_eq!(format_utc(i64::MAX as u64 + 1), (i64::MAX as u64 + 1).to_string());
+        assert_eq!(format_utc(u64::MAX), u64::MAX.to_string());
+    }
+}
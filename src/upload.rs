@@ -0,0 +1,103 @@
+use crate::errors::HurlResult;
+use log::trace;
+use reqwest::Body;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How often the progress line is redrawn
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A request body streamed from `path`, or from stdin for `-`
+///
+/// Files are sent with a Content-Length, stdin with chunked transfer
+/// encoding. Nothing is read into memory up front.
+pub fn body(path: &Path, show_progress: bool) -> HurlResult<Body>{
+    if path == Path::new("-"){
+        trace!("Streaming body from stdin");
+        return Ok(Body::new(Progress::new(io::stdin(), None, show_progress)));
+    }
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    trace!("Streaming {} bytes from {}", len, path.display());
+    Ok(Body::sized(Progress::new(file, Some(len), show_progress), len))
+}
+
+/// A reader which reports how much has been read on stderr
+struct Progress<R>{
+    inner: R,
+    sent: u64,
+    total: Option<u64>,
+    show: bool,
+    last: Option<Instant>,
+    finished: bool,
+}
+
+impl<R> Progress<R>{
+    fn new(inner: R, total: Option<u64>, show: bool) -> Self{
+        Progress{
+            inner,
+            sent: 0,
+            total,
+            show,
+            last: None,
+            finished: false,
+        }
+    }
+
+    fn report(&mut self, done: bool){
+        if !self.show{
+            return;
+        }
+        let now = Instant::now();
+        if !done && self.last.is_some_and(|l| now - l < PROGRESS_INTERVAL){
+            return;
+        }
+        self.last = Some(now);
+        let line = match self.total{
+            Some(total) if total > 0 => format!(
+                "Uploaded {} of {} ({}%)",
+                human_size(self.sent),
+                human_size(total),
+                self.sent * 100 / total
+            ),
+            _ => format!("Uploaded {}", human_size(self.sent)),
+        };
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "\r{:<40}", line);
+        if done{
+            let _ = writeln!(stderr);
+        }
+        let _ = stderr.flush();
+    }
+}
+
+impl<R: Read> Read for Progress<R>{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>{
+        let n = self.inner.read(buf)?;
+        self.sent += n as u64;
+        if n > 0{
+            self.report(false);
+        } else if !self.finished{
+            self.finished = true;
+            self.report(true);
+        }
+        Ok(n)
+    }
+}
+
+fn human_size(bytes: u64) -> String{
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1{
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0{
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
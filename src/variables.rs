@@ -0,0 +1,274 @@
+use crate::assertions;
+use crate::errors::{Error, HurlResult};
+use log::{trace, warn};
+use reqwest::header::HeaderMap;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Where a captured value is read from in the response
+#[derive(Debug, Clone)]
+pub enum CaptureSource{
+    /// A jq-like path into the JSON body, e.g. `.access_token`
+    Json(String),
+    /// A response header, given as `header:X-Token`
+    Header(String),
+    /// A cookie set by the response, given as `cookie:sid`
+    Cookie(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Capture{
+    pub name: String,
+    pub source: CaptureSource,
+}
+
+pub fn parse_capture(src: &str) -> HurlResult<Capture>{
+    let idx = match src.find('='){
+        Some(idx) => idx,
+        None => return Err(Error::InvalidCapture(src.to_owned())),
+    };
+    let (name, source) = src.split_at(idx);
+    let name = name.trim();
+    let source = source[1..].trim();
+    if name.is_empty() || source.is_empty(){
+        return Err(Error::InvalidCapture(src.to_owned()));
+    }
+    let source = if let Some(header) = source.strip_prefix("header:"){
+        CaptureSource::Header(header.to_owned())
+    } else if let Some(cookie) = source.strip_prefix("cookie:"){
+        CaptureSource::Cookie(cookie.to_owned())
+    } else if source.starts_with('.'){
+        CaptureSource::Json(source.to_owned())
+    } else {
+        return Err(Error::InvalidCapture(src.to_owned()));
+    };
+    Ok(Capture{
+        name: name.to_owned(),
+        source,
+    })
+}
+
+/// Extract every capture from a response
+///
+/// A capture which does not match anything in the response is an error
+/// so that a broken login step does not silently feed empty values to
+/// the requests after it.
+pub fn capture(
+    captures: &[Capture],
+    headers: &HeaderMap,
+    cookies: &[(String, String)],
+    body: &str,
+) -> HurlResult<Vec<(String, String)>>{
+    let mut json: Option<Value> = None;
+    let mut values = Vec::new();
+    for c in captures.iter(){
+        let value = match &c.source{
+            CaptureSource::Header(name) => headers
+                .get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_owned()),
+            CaptureSource::Cookie(name) => cookies
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.clone()),
+            CaptureSource::Json(path) => {
+                if json.is_none(){
+                    json = serde_json::from_str(body).ok();
+                }
+                json.as_ref()
+                    .and_then(|j| assertions::lookup(j, path))
+                    .map(|v| match v{
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    })
+            }
+        };
+        match value{
+            Some(value) => {
+                trace!("Captured {}", c.name);
+                values.push((c.name.clone(), value));
+            }
+            None => return Err(Error::CaptureNotFound(c.name.clone())),
+        }
+    }
+    Ok(values)
+}
+
+/// Replace every variable reference in `s`
+///
+/// `{{name}}` refers to a session variable, while `{{env.NAME}}` and
+/// `${NAME}` refer to environment variables. Unknown variables are
+/// replaced with an empty string unless `strict` is set, in which case
+/// they are an error.
+pub fn interpolate(s: &str, vars: &HashMap<String, String>, strict: bool) -> HurlResult<String>{
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    loop{
+        let next = match (rest.find("{{"), rest.find("${")){
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let start = match next{
+            Some(start) => start,
+            None => break,
+        };
+        let (close, env_only) = if rest[start..].starts_with("{{"){
+            ("}}", false)
+        } else {
+            ("}", true)
+        };
+        let end = match rest[start..].find(close){
+            Some(end) => start + end,
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        let name = rest[start + 2..end].trim();
+        let value = if env_only{
+            env::var(name).ok()
+        } else if let Some(name) = name.strip_prefix("env."){
+            env::var(name).ok()
+        } else {
+            vars.get(name).cloned()
+        };
+        match value{
+            Some(value) => result.push_str(&value),
+            None if strict => return Err(Error::UndefinedVariable(name.to_owned())),
+            None => warn!("Undefined variable: {}", name),
+        }
+        rest = &rest[end + close.len()..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Load `KEY=VALUE` lines from a .env file into the environment
+///
+/// Blank lines, `#` comments and a leading `export` are ignored and
+/// values may be quoted. Variables which are already set are kept.
+pub fn load_env_file(path: &Path) -> HurlResult<()>{
+    let content = fs::read_to_string(path)?;
+    for (i, line) in content.lines().enumerate(){
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#'){
+            continue;
+        }
+        let line = line.trim_start_matches("export ").trim_start();
+        let idx = match line.find('='){
+            Some(idx) => idx,
+            None => return Err(Error::InvalidEnvFile(path.to_owned(), i + 1)),
+        };
+        let key = line[..idx].trim();
+        let mut value = line[idx + 1..].trim();
+        if value.len() >= 2
+            && ((value.starts_with('"') && value.ends_with('"'))
+                || (value.starts_with('\'') && value.ends_with('\'')))
+        {
+            value = &value[1..value.len() - 1];
+        }
+        if env::var_os(key).is_none(){
+            trace!("Setting {} from env file", key);
+            env::set_var(key, value);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn parses_captures() {
+        let c = parse_capture("token = .data.access_token").unwrap();
+        assert_eq!(c.name, "token");
+        assert!(matches!(c.source, CaptureSource::Json(ref p) if p == ".data.access_token"));
+        assert!(matches!(parse_capture("t=header:X-Token").unwrap().source, CaptureSource::Header(ref h) if h == "X-Token"));
+        assert!(matches!(parse_capture("s=cookie:sid").unwrap().source, CaptureSource::Cookie(ref c) if c == "sid"));
+        for bad in &["token", "=.a", "token=", "token=data"] {
+            assert!(parse_capture(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn captures_from_the_response() {
+        let captures: Vec<Capture> = ["t=.data.token", "n=.data.count", "h=header:x-token", "s=cookie:sid"]
+            .iter()
+            .map(|c| parse_capture(c).unwrap())
+            .collect();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-token", HeaderValue::from_static("abc"));
+        let cookies = vec![("sid".to_owned(), "42".to_owned())];
+        let body = r#"{"data": {"token": "xyz", "count": 3}}"#;
+        let values = capture(&captures, &headers, &cookies, body).unwrap();
+        let expected = [("t", "xyz"), ("n", "3"), ("h", "abc"), ("s", "42")];
+        let values: Vec<(&str, &str)> = values.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect();
+        assert_eq!(values, expected);
+
+        let missing = [parse_capture("t=.missing").unwrap()];
+        assert!(matches!(
+            capture(&missing, &headers, &cookies, body),
+            Err(Error::CaptureNotFound(ref name)) if name == "t"
+        ));
+    }
+
+    #[test]
+    fn interpolates_session_variables() {
+        let mut vars = HashMap::new();
+        vars.insert("id".to_owned(), "7".to_owned());
+        assert_eq!(interpolate("/users/{{id}}/{{ id }}", &vars, false).unwrap(), "/users/7/7");
+        assert_eq!(interpolate("/users/{{missing}}", &vars, false).unwrap(), "/users/");
+        assert_eq!(interpolate("{{id", &vars, false).unwrap(), "{{id");
+    }
+
+    #[test]
+    fn interpolates_environment_variables() {
+        env::set_var("HURL_TEST_INTERPOLATE", "v");
+        env::remove_var("HURL_TEST_UNSET");
+        let vars = HashMap::new();
+        assert_eq!(
+            interpolate("{{env.HURL_TEST_INTERPOLATE}}-${HURL_TEST_INTERPOLATE}-${ HURL_TEST_INTERPOLATE }", &vars, false).unwrap(),
+            "v-v-v"
+        );
+        assert_eq!(interpolate("a${HURL_TEST_UNSET}b", &vars, false).unwrap(), "ab");
+        assert_eq!(interpolate("${HURL_TEST_INTERPOLATE", &vars, false).unwrap(), "${HURL_TEST_INTERPOLATE");
+        assert_eq!(interpolate("{{env.HURL_TEST_INTERPOLATE}}", &vars, true).unwrap(), "v");
+        for s in &["${HURL_TEST_UNSET}", "{{env.HURL_TEST_UNSET}}", "{{missing}}"] {
+            assert!(matches!(interpolate(s, &vars, true), Err(Error::UndefinedVariable(_))), "{}", s);
+        }
+    }
+
+    #[test]
+    fn loads_env_files() {
+        let path = env::temp_dir().join("hurl-test.env");
+        fs::write(
+            &path,
+            "# a comment\n\
+             \n\
+             HURL_TEST_PLAIN = plain value\n\
+             export HURL_TEST_EXPORTED=exported\n\
+             HURL_TEST_DOUBLE=\"double quoted\"\n\
+             HURL_TEST_SINGLE='single=quoted'\n\
+             HURL_TEST_KEPT=from file\n",
+        )
+        .unwrap();
+        env::set_var("HURL_TEST_KEPT", "from environment");
+        let result = load_env_file(&path);
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+        assert_eq!(env::var("HURL_TEST_PLAIN").unwrap(), "plain value");
+        assert_eq!(env::var("HURL_TEST_EXPORTED").unwrap(), "exported");
+        assert_eq!(env::var("HURL_TEST_DOUBLE").unwrap(), "double quoted");
+        assert_eq!(env::var("HURL_TEST_SINGLE").unwrap(), "single=quoted");
+        assert_eq!(env::var("HURL_TEST_KEPT").unwrap(), "from environment");
+
+        let path = env::temp_dir().join("hurl-test-bad.env");
+        fs::write(&path, "HURL_TEST_OK=1\nnot a variable\n").unwrap();
+        let result = load_env_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(Error::InvalidEnvFile(_, 2))));
+    }
+}